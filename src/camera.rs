@@ -0,0 +1,405 @@
+use bevy::input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+
+use crate::celestial_body::CelestialBody;
+use crate::frame::{FramePose, FrameView};
+use crate::scenario::ZoomPresets;
+use crate::selection::Selected;
+use crate::units::SimulationUnits;
+use crate::{MainCamera, WorldCursor};
+
+pub struct CameraPlugin;
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CameraController>().add_systems(
+            Update,
+            (
+                reframe_camera,
+                (
+                    cycle_camera_mode,
+                    zoom_to_preset,
+                    zoom_with_wheel,
+                    pan_with_middle_drag,
+                ),
+                follow_focus,
+                ease_camera,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// What the camera keeps in view.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Stays where it is panned to.
+    #[default]
+    Free,
+    /// Follows the selected body.
+    Selected,
+    /// Follows the centre of mass of all bodies.
+    Barycentre,
+    /// Follows the heaviest body.
+    Heaviest,
+    /// Zooms and pans to keep every body on screen.
+    FitAll,
+}
+
+impl CameraMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Free => Self::Selected,
+            Self::Selected => Self::Barycentre,
+            Self::Barycentre => Self::Heaviest,
+            Self::Heaviest => Self::FitAll,
+            Self::FitAll => Self::Free,
+        }
+    }
+}
+
+/// Rate the camera closes on its target, per second.
+const CAMERA_SMOOTHING: f32 = 8.0;
+
+/// Scale change per line of mouse wheel.
+const ZOOM_STEP: f32 = 1.1;
+
+/// Pixels of a touchpad scroll that count as one line of mouse wheel.
+const PIXELS_PER_LINE: f32 = 50.0;
+
+/// Range of the projection scale, in world pixels per screen pixel.
+const MIN_SCALE: f32 = 1.0e-4;
+const MAX_SCALE: f32 = 1.0e4;
+
+/// Screen space left around the bodies in `CameraMode::FitAll`, as a fraction of the window.
+const FIT_MARGIN: f32 = 0.1;
+
+/// Where the main camera is heading.
+///
+/// The camera eases from `position` towards `target` and `scale`, so switching modes glides
+/// rather than jumps. Positions are in the coordinates of the reference frame, and the camera
+/// turns with the frame.
+#[derive(Resource, Clone, Debug)]
+pub struct CameraController {
+    pub mode: CameraMode,
+    /// Panning and zooming away from what the mode follows, in world pixels.
+    offset: Vec2,
+    target: Vec2,
+    scale: f32,
+    position: Vec2,
+    /// The frame as of this frame's update.
+    pose: FramePose,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self {
+            mode: CameraMode::default(),
+            offset: Vec2::ZERO,
+            target: Vec2::ZERO,
+            scale: 1.0,
+            position: Vec2::ZERO,
+            pose: FramePose::INERTIAL,
+        }
+    }
+}
+
+impl CameraController {
+    /// Switches mode, centring on whatever the new one follows.
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        self.mode = mode;
+        self.offset = Vec2::ZERO;
+    }
+
+    /// Moves the target, and the offset from the focus with it.
+    fn pan(&mut self, delta: Vec2) {
+        self.target += delta;
+        self.offset += delta;
+    }
+}
+
+/// Scales the view about `anchor`, so the world point under it stays put.
+fn zoom_about(translation: Vec2, anchor: Vec2, factor: f32) -> Vec2 {
+    anchor + (translation - anchor) * factor
+}
+
+/// Moves `current` towards `target`, closing the gap at `rate` per second whatever the frame rate.
+fn ease(current: f32, target: f32, rate: f32, dt: f32) -> f32 {
+    target + (current - target) * (-rate * dt).exp()
+}
+
+/// The centre and projection scale that fit every position into a viewport of `size` pixels.
+fn fit(positions: impl IntoIterator<Item = Vec2>, size: Vec2) -> Option<(Vec2, f32)> {
+    let mut positions = positions.into_iter();
+    let first = positions.next()?;
+    let (min, max) = positions.fold((first, first), |(min, max), position| {
+        (min.min(position), max.max(position))
+    });
+    let extent = (max - min) / (size * (1.0 - 2.0 * FIT_MARGIN));
+    // No closer than one world pixel per screen pixel, so a lone body isn't blown up
+    Some((
+        (min + max) / 2.0,
+        extent.max_element().clamp(1.0, MAX_SCALE),
+    ))
+}
+
+/// The centre of mass of the bodies.
+fn barycentre<'a>(bodies: impl IntoIterator<Item = &'a CelestialBody>) -> Option<Vec2> {
+    let (moment, mass) = bodies
+        .into_iter()
+        .fold((Vec2::ZERO, 0.0), |(moment, mass), body| {
+            (moment + body.mass * body.position, mass + body.mass)
+        });
+    (mass > 0.0).then(|| moment / mass)
+}
+
+/// Keeps the camera where it is on screen when the reference frame is switched
+fn reframe_camera(frame: FrameView, mut controller: ResMut<CameraController>) {
+    let pose = frame.now();
+    if frame.is_changed() {
+        let old = controller.pose;
+        let reframe = |position| pose.to_frame(old.to_world(position));
+        controller.position = reframe(controller.position);
+        controller.target = reframe(controller.target);
+    }
+    controller.pose = pose;
+}
+
+/// Cycles the camera mode when V is pressed
+fn cycle_camera_mode(keys: Res<ButtonInput<KeyCode>>, mut controller: ResMut<CameraController>) {
+    if keys.just_pressed(KeyCode::KeyV) {
+        let mode = controller.mode.next();
+        controller.set_mode(mode);
+        info!("Camera mode: {:?}", mode);
+    }
+}
+
+/// Zooms out to the scenario's zoom presets, the first on startup and the rest on the number keys
+fn zoom_to_preset(
+    keys: Res<ButtonInput<KeyCode>>,
+    presets: Res<ZoomPresets>,
+    units: Res<SimulationUnits>,
+    windows: Query<&Window>,
+    mut controller: ResMut<CameraController>,
+    mut camera: Query<&mut OrthographicProjection, With<MainCamera>>,
+) {
+    const KEYS: [KeyCode; 9] = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+    let pressed = KEYS.iter().position(|&key| keys.just_pressed(key));
+    let index = match pressed {
+        Some(index) => index,
+        None if presets.is_changed() => 0,
+        None => return,
+    };
+    let Some(&distance) = presets.0.get(index) else {
+        return;
+    };
+
+    let half_height = windows.single().height() / 2.0;
+    controller.scale = distance * units.pixels_per_length / half_height;
+    match controller.mode {
+        // Fitting would undo the zoom straight away
+        CameraMode::Free | CameraMode::FitAll => {
+            controller.set_mode(CameraMode::Free);
+            controller.target = Vec2::ZERO;
+        }
+        _ => controller.offset = Vec2::ZERO,
+    }
+
+    // A new scenario starts in place rather than gliding in
+    if pressed.is_none() {
+        camera.single_mut().scale = controller.scale;
+        controller.position = controller.target;
+    }
+}
+
+/// Zooms with the mouse wheel, keeping the point under the cursor in place
+fn zoom_with_wheel(
+    mut wheel: EventReader<MouseWheel>,
+    cursor: WorldCursor,
+    mut controller: ResMut<CameraController>,
+    mut camera: Query<&mut OrthographicProjection, With<MainCamera>>,
+) {
+    let lines: f32 = wheel
+        .read()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+        })
+        .sum();
+    if lines == 0.0 || cursor.over_ui() {
+        return;
+    }
+    let Some(anchor) = cursor.position() else {
+        return;
+    };
+    let anchor = controller.pose.to_frame(anchor);
+
+    // Fitting would undo the zoom straight away
+    if controller.mode == CameraMode::FitAll {
+        controller.mode = CameraMode::Free;
+    }
+
+    let mut projection = camera.single_mut();
+    let factor =
+        (projection.scale * ZOOM_STEP.powf(-lines)).clamp(MIN_SCALE, MAX_SCALE) / projection.scale;
+    projection.scale *= factor;
+    controller.position = zoom_about(controller.position, anchor, factor);
+
+    controller.scale = (controller.scale * factor).clamp(MIN_SCALE, MAX_SCALE);
+    let delta = zoom_about(controller.target, anchor, factor) - controller.target;
+    controller.pan(delta);
+}
+
+/// Drags the view around while the middle mouse button is held
+fn pan_with_middle_drag(
+    mut motion: EventReader<MouseMotion>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    mut controller: ResMut<CameraController>,
+    camera: Query<&OrthographicProjection, With<MainCamera>>,
+) {
+    let delta: Vec2 = motion.read().map(|event| event.delta).sum();
+    if !mouse_button_input.pressed(MouseButton::Middle) || delta == Vec2::ZERO {
+        return;
+    }
+
+    // Screen y points down, world y up, and the camera lines up with the frame
+    let delta = Vec2::new(-delta.x, delta.y) * camera.single().scale;
+    controller.position += delta;
+    controller.pan(delta);
+}
+
+/// Points the target at whatever the mode follows
+fn follow_focus(
+    mut controller: ResMut<CameraController>,
+    windows: Query<&Window>,
+    selected: Query<&CelestialBody, With<Selected>>,
+    bodies: Query<&CelestialBody>,
+) {
+    let pose = controller.pose;
+    let focus = match controller.mode {
+        CameraMode::Free => None,
+        CameraMode::Selected => selected.get_single().ok().map(|body| body.position),
+        CameraMode::Barycentre => barycentre(&bodies),
+        CameraMode::Heaviest => bodies
+            .iter()
+            .max_by(|a, b| a.mass.total_cmp(&b.mass))
+            .map(|body| body.position),
+        CameraMode::FitAll => {
+            let size = windows.single().size();
+            let fitted = fit(bodies.iter().map(|body| pose.to_frame(body.position)), size);
+            fitted.map(|(centre, scale)| {
+                controller.scale = scale;
+                pose.to_world(centre)
+            })
+        }
+    };
+    if let Some(focus) = focus {
+        controller.target = pose.to_frame(focus) + controller.offset;
+    }
+}
+
+/// Glides the camera towards its target, and turns it with the frame
+pub fn ease_camera(
+    time: Res<Time>,
+    mut controller: ResMut<CameraController>,
+    mut camera: Query<(&mut OrthographicProjection, &mut Transform), With<MainCamera>>,
+) {
+    let (mut projection, mut transform) = camera.single_mut();
+    let dt = time.delta_seconds();
+
+    // Zooming eases in log space, so it feels the same at every scale
+    let scale = ease(
+        projection.scale.ln(),
+        controller.scale.ln(),
+        CAMERA_SMOOTHING,
+        dt,
+    )
+    .exp();
+    if projection.scale != scale {
+        projection.scale = scale;
+    }
+    let (position, target) = (controller.position, controller.target);
+    controller.position = Vec2::new(
+        ease(position.x, target.x, CAMERA_SMOOTHING, dt),
+        ease(position.y, target.y, CAMERA_SMOOTHING, dt),
+    );
+
+    let translation = controller.pose.to_world(controller.position);
+    let rotation = controller.pose.rotation();
+    if transform.translation.truncate() != translation || transform.rotation != rotation {
+        transform.translation = translation.extend(transform.translation.z);
+        transform.rotation = rotation;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zooming_keeps_the_anchor_in_place() {
+        let translation = Vec2::new(100.0, -40.0);
+        let anchor = Vec2::new(130.0, 20.0);
+        let factor = 0.5;
+        let zoomed = zoom_about(translation, anchor, factor);
+
+        // The anchor sits the same number of screen pixels from the centre before and after
+        let before = (anchor - translation) / 1.0;
+        let after = (anchor - zoomed) / factor;
+        assert!(before.distance(after) < 1.0e-4, "{before} != {after}");
+    }
+
+    #[test]
+    fn easing_closes_the_gap_at_any_frame_rate() {
+        let one_step = ease(0.0, 10.0, CAMERA_SMOOTHING, 0.1);
+        let two_steps = ease(
+            ease(0.0, 10.0, CAMERA_SMOOTHING, 0.05),
+            10.0,
+            CAMERA_SMOOTHING,
+            0.05,
+        );
+        assert!((one_step - two_steps).abs() < 1.0e-4);
+        assert!(one_step > 0.0 && one_step < 10.0);
+        assert!((ease(0.0, 10.0, CAMERA_SMOOTHING, 10.0) - 10.0).abs() < 1.0e-4);
+    }
+
+    #[test]
+    fn fit_frames_every_body() {
+        let size = Vec2::new(800.0, 600.0);
+        let positions = [
+            Vec2::new(-100.0, 50.0),
+            Vec2::new(300.0, -10.0),
+            Vec2::new(0.0, 2000.0),
+        ];
+        let (centre, scale) = fit(positions, size).unwrap();
+        for position in positions {
+            // Within the window, short of the margin
+            let screen = (position - centre) / scale;
+            assert!(screen.x.abs() <= size.x / 2.0 * (1.0 - 2.0 * FIT_MARGIN) + 1.0e-3);
+            assert!(screen.y.abs() <= size.y / 2.0 * (1.0 - 2.0 * FIT_MARGIN) + 1.0e-3);
+        }
+        assert_eq!(fit([Vec2::ZERO; 0], size), None);
+    }
+
+    #[test]
+    fn barycentre_weights_by_mass() {
+        let bodies = [
+            CelestialBody::default()
+                .with_mass(3.0)
+                .with_position(Vec2::new(0.0, 0.0)),
+            CelestialBody::default()
+                .with_mass(1.0)
+                .with_position(Vec2::new(4.0, 8.0)),
+        ];
+        assert_eq!(barycentre(&bodies), Some(Vec2::new(1.0, 2.0)));
+    }
+}
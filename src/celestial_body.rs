@@ -1,25 +1,82 @@
-use bevy::{math::NormedVectorSpace, prelude::*};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 use std::f32::consts::PI;
 
-// TODO: This is probs going to break things.
-use crate::MainCamera;
+use crate::clock::{clock_is_running, clock_is_running_forwards, SimulationClock};
+use crate::collision::{CollisionOutcome, CollisionPolicy};
+use crate::gravity::{GravityConfig, GravitySolver};
+use crate::integrator::{AdaptiveTimestep, Integrator};
+use crate::orbital_elements::OrbitalElements;
+use crate::prediction::Conic;
+use crate::selection::BodyPicker;
+use crate::units::{apply_length_unit, SimulationUnits};
+use crate::{MainCamera, WorldCursor};
+
+#[derive(Default)]
+pub struct CelestialBodyPlugin {
+    /// Leaves out everything that needs a window, such as mouse interaction and gizmos.
+    pub headless: bool,
+}
 
-pub struct CelestialBodyPlugin;
 impl Plugin for CelestialBodyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0)); //FIXME: This is broken
+        // Zero gravity for a space-like environment, and a fixed timestep so runs are repeatable
+        let units = SimulationUnits::default();
+        app.insert_resource(RapierConfiguration {
+            gravity: Vec2::ZERO,
+            timestep_mode: TimestepMode::Fixed {
+                dt: 1.0 / 64.0,
+                substeps: 1,
+            },
+            ..RapierConfiguration::new(units.pixels_per_length)
+        });
+        app.add_plugins(
+            RapierPhysicsPlugin::<NoUserData>::default()
+                .with_length_unit(units.pixels_per_length)
+                .in_fixed_schedule(),
+        );
+
+        app.add_systems(
+            FixedUpdate,
+            (
+                apply_length_unit.before(PhysicsSet::SyncBackend),
+                apply_gravity
+                    .run_if(clock_is_running)
+                    .before(PhysicsSet::SyncBackend),
+                resolve_collisions
+                    .run_if(clock_is_running_forwards)
+                    .after(PhysicsSet::Writeback),
+                discard_collisions
+                    .run_if(not(clock_is_running_forwards))
+                    .after(PhysicsSet::Writeback),
+            ),
+        )
+        .init_resource::<CelestialBodyAssets>()
+        .init_resource::<CollisionPolicy>()
+        .init_resource::<GravityConfig>()
+        .insert_resource(units)
+        .init_resource::<GravitySolver>()
+        .init_resource::<Integrator>()
+        .init_resource::<AdaptiveTimestep>();
+
+        if self.headless {
+            return;
+        }
 
         #[cfg(debug_assertions)]
         app.add_plugins(RapierDebugRenderPlugin::default());
 
-        app.add_systems(FixedFirst, reset_forces.in_set(PhysicsSet::SyncBackend))
-            .add_systems(FixedUpdate, apply_gravity.in_set(PhysicsSet::SyncBackend))
-            .add_systems(FixedUpdate, combine_bodies)
-            .init_resource::<CelestialBodyAssets>();
-
         app.init_resource::<MouseDragState>();
-        app.add_systems(Update, spawn_on_mouse_drag);
+        app.add_systems(
+            Update,
+            (
+                (spawn_on_mouse_drag, draw_spawn_preview).chain(),
+                toggle_solvers,
+                cycle_collision_policy,
+                keep_bodies_visible,
+            ),
+        );
 
         #[cfg(debug_assertions)]
         app.add_systems(Update, debug_draw_two_body_connection);
@@ -27,8 +84,11 @@ impl Plugin for CelestialBodyPlugin {
 }
 
 // https://bevy-cheatbook.github.io/programming/res.html
+//
+// Without a renderer there are no images to load, so every handle is left as the default.
 #[derive(Resource, Clone)]
 pub struct CelestialBodyAssets {
+    asset_server: Option<AssetServer>,
     moon: Handle<Image>,
     earth: Handle<Image>,
     sun: Handle<Image>,
@@ -36,44 +96,112 @@ pub struct CelestialBodyAssets {
 
 impl FromWorld for CelestialBodyAssets {
     fn from_world(world: &mut World) -> Self {
-        let asset_server = world.resource::<AssetServer>();
-        Self {
-            moon: asset_server.load("sprites/moon.png"),
-            earth: asset_server.load("sprites/earth.png"),
-            sun: asset_server.load("sprites/sun.png"),
+        let asset_server = world
+            .contains_resource::<Assets<Image>>()
+            .then(|| world.resource::<AssetServer>().clone());
+        let mut assets = Self {
+            asset_server,
+            moon: Handle::default(),
+            earth: Handle::default(),
+            sun: Handle::default(),
+        };
+        assets.moon = assets.load_sprite("sprites/moon.png");
+        assets.earth = assets.load_sprite("sprites/earth.png");
+        assets.sun = assets.load_sprite("sprites/sun.png");
+        assets
+    }
+}
+
+impl CelestialBodyAssets {
+    /// Loads an image for a sprite, if there is a renderer to show it.
+    pub fn load_sprite(&self, path: &str) -> Handle<Image> {
+        match &self.asset_server {
+            Some(asset_server) => asset_server.load(path.to_string()),
+            None => Handle::default(),
+        }
+    }
+
+    /// The default image for a kind of body.
+    pub fn image(&self, kind: CelestialBodyKind) -> Handle<Image> {
+        match kind {
+            CelestialBodyKind::Moon => self.moon.clone(),
+            CelestialBodyKind::Planet => self.earth.clone(),
+            CelestialBodyKind::Star => self.sun.clone(),
+        }
+    }
+
+    /// Creates a sprite for the body, picking the image from its kind and sizing it to its radius.
+    pub fn sprite_bundle(&self, body: &CelestialBody) -> SpriteBundle {
+        let radius = body.radius();
+        SpriteBundle {
+            texture: self.image(body.kind),
+            sprite: Sprite {
+                custom_size: Some(Vec2::new(2.0 * radius, 2.0 * radius)), // Set the desired size here
+                ..Default::default()
+            },
+            transform: Transform::from_translation(body.position.extend(0.0)),
+            ..Default::default()
         }
     }
 }
 
-// TODO: Make this a setup system and change sprites depending on mass (ie asteriod, moon, planet, star, blackhole!)
-pub fn add_sprite(
-    commands: &mut Commands,
-    entity: Entity,
-    image_assets: &CelestialBodyAssets,
-    mass: f32,
+/// Smallest size a body is drawn at, in pixels on screen.
+const MIN_SPRITE_SIZE: f32 = 3.0;
+
+/// Draws bodies at least `MIN_SPRITE_SIZE` across, so small bodies stay visible when zoomed out
+fn keep_bodies_visible(
+    camera: Query<&OrthographicProjection, With<MainCamera>>,
+    mut query: Query<(&CelestialBody, &mut Sprite)>,
 ) {
-    let image = if mass < 1.0 {
-        image_assets.moon.clone()
-    } else if mass < 7.0 {
-        image_assets.earth.clone()
-    } else {
-        image_assets.sun.clone()
+    let Ok(projection) = camera.get_single() else {
+        return;
     };
-    let radius = CelestialBody::radius_from_mass(mass);
-    commands.entity(entity).insert(SpriteBundle {
-        texture: image,
-        sprite: Sprite {
-            custom_size: Some(Vec2::new(2.0 * radius, 2.0 * radius)), // Set the desired size here
-            ..Default::default()
-        },
-        ..Default::default()
-    });
+    let min_size = MIN_SPRITE_SIZE * projection.scale;
+    for (body, mut sprite) in &mut query {
+        let size = Some(Vec2::splat((2.0 * body.radius()).max(min_size)));
+        if sprite.custom_size != size {
+            sprite.custom_size = size;
+        }
+    }
 }
 
+/// The broad category of a body, used to pick its sprite.
+// TODO: Add asteroids and black holes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CelestialBodyKind {
+    #[default]
+    Moon,
+    Planet,
+    Star,
+}
+
+impl CelestialBodyKind {
+    pub fn from_mass(mass: f32) -> Self {
+        if mass < 1.0 {
+            Self::Moon
+        } else if mass < 7.0 {
+            Self::Planet
+        } else {
+            Self::Star
+        }
+    }
+}
+
+/// A body in the N-body simulation.
+///
+/// This is the source of truth for the dynamics, the `Transform` is only written from it.
+/// Only entities with this component attract, merge or leave trails.
+#[derive(Component, Clone, Debug, PartialEq)]
 pub struct CelestialBody {
     pub position: Vec2,
     pub velocity: Vec2,
     pub mass: f32,
+    /// Angular velocity in radians per second, anticlockwise.
+    pub spin: f32,
+    /// Mass per unit area, sets the radius of the body.
+    pub density: f32,
+    pub name: String,
+    pub kind: CelestialBodyKind,
 }
 
 impl Default for CelestialBody {
@@ -82,193 +210,330 @@ impl Default for CelestialBody {
             position: Vec2::default(),
             velocity: Vec2::default(),
             mass: 1.0,
+            spin: 0.0,
+            density: Self::default_density(),
+            name: String::new(),
+            kind: CelestialBodyKind::from_mass(1.0),
         }
     }
 }
 
 impl CelestialBody {
     pub fn with_position(&self, position: Vec2) -> Self {
-        CelestialBody { position, ..*self }
+        CelestialBody {
+            position,
+            ..self.clone()
+        }
     }
     pub fn with_velocity(&self, velocity: Vec2) -> Self {
-        CelestialBody { velocity, ..*self }
+        CelestialBody {
+            velocity,
+            ..self.clone()
+        }
     }
+    pub fn with_spin(&self, spin: f32) -> Self {
+        CelestialBody {
+            spin,
+            ..self.clone()
+        }
+    }
+    /// Sets the mass, and the kind that goes with it.
     pub fn with_mass(&self, mass: f32) -> Self {
         assert!(mass > 0.0);
-        CelestialBody { mass, ..*self }
+        CelestialBody {
+            mass,
+            kind: CelestialBodyKind::from_mass(mass),
+            ..self.clone()
+        }
     }
-
     /// Calculates the body's radius given mass.
     ///
     /// Assumes a constant density.
     pub fn radius_from_mass(mass: f32) -> f32 {
         5.0 * (mass / 2.0 * PI).sqrt()
     }
+
+    /// The density that reproduces [`Self::radius_from_mass`].
+    pub fn default_density() -> f32 {
+        1.0 / (PI * Self::radius_from_mass(1.0).powi(2))
+    }
+
+    /// Calculates the body's radius from its mass and density.
+    pub fn radius(&self) -> f32 {
+        (self.mass / (PI * self.density)).sqrt()
+    }
+
+    /// Moment of inertia of a uniform disc, sized by [`Self::radius_from_mass`].
+    pub fn moment_of_inertia(&self) -> f32 {
+        0.5 * self.mass * Self::radius_from_mass(self.mass).powi(2)
+    }
+
+    /// Spin plus orbital angular momentum about a point moving with `origin_velocity`.
+    pub fn angular_momentum(&self, origin: Vec2, origin_velocity: Vec2) -> f32 {
+        self.moment_of_inertia() * self.spin
+            + self.mass * (self.position - origin).perp_dot(self.velocity - origin_velocity)
+    }
 }
 
+/// The body that exerts the largest force on this one.
 #[derive(Component, Default)]
-struct TwoBodyProblem {
-    entity: Option<Entity>,
-    force: Option<f32>,
+pub struct TwoBodyProblem {
+    pub entity: Option<Entity>,
 }
 
 impl TwoBodyProblem {
-    fn update(&mut self, entity: Entity, force: f32) {
+    fn update(&mut self, entity: Entity) {
         *self = Self {
             entity: Some(entity),
-            force: Some(force),
         };
     }
 }
 
-/// Spawns a celesital body
+/// Holds a body still, while it keeps attracting the others.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Pinned;
+
+/// Everything needed to spawn a celestial body.
 ///
-// TODO: Should I use a Bundle here?
-pub fn add_celestial_body(commands: &mut Commands, entity: Entity, body: CelestialBody) {
-    let radius = CelestialBody::radius_from_mass(body.mass);
-    commands
-        .entity(entity)
-        .insert(RigidBody::Dynamic)
-        .insert(Collider::ball(radius))
-        .insert(TransformBundle::from(Transform::from_xyz(
-            body.position.x,
-            body.position.y,
-            0f32,
-        )))
-        .insert(ExternalForce {
-            force: Vec2::new(0.0, 0.0),
-            torque: 0.0,
-        })
-        .insert(AdditionalMassProperties::Mass(body.mass)) // Final mass of the body
-        .insert(ColliderMassProperties::Density(0.0))
-        .insert(ReadMassProperties::default())
-        .insert(Velocity {
-            linvel: body.velocity,
-            angvel: 0.0,
-        })
-        .insert(ActiveEvents::COLLISION_EVENTS)
-        .insert(TwoBodyProblem::default());
+/// Bodies are kinematic so Rapier only detects collisions, their motion comes from the integrator.
+#[derive(Bundle)]
+pub struct CelestialBodyBundle {
+    pub body: CelestialBody,
+    pub rigid_body: RigidBody,
+    pub collider: Collider,
+    pub active_events: ActiveEvents,
+    pub active_collision_types: ActiveCollisionTypes,
+    pub two_body_problem: TwoBodyProblem,
+    pub name: Name,
+    pub sprite: SpriteBundle,
 }
 
-/// Zeros out external_forces and two body problem influence
-fn reset_forces(mut query: Query<(&mut ExternalForce, &mut TwoBodyProblem)>) {
-    for (mut external_forces, mut two_body_problem) in &mut query {
-        external_forces.force = Vec2::default();
-        *two_body_problem = TwoBodyProblem::default();
+impl CelestialBodyBundle {
+    pub fn new(body: CelestialBody, assets: &CelestialBodyAssets) -> Self {
+        Self {
+            rigid_body: RigidBody::KinematicPositionBased,
+            collider: Collider::ball(body.radius()),
+            active_events: ActiveEvents::COLLISION_EVENTS,
+            active_collision_types: ActiveCollisionTypes::default()
+                | ActiveCollisionTypes::KINEMATIC_KINEMATIC,
+            two_body_problem: TwoBodyProblem::default(),
+            name: Name::new(body.name.clone()),
+            sprite: assets.sprite_bundle(&body),
+            body,
+        }
     }
 }
 
-/// Applies gravitational attraction contributions from all bodies.
-// FIXME: Should use a "CelestialBody" component to differentiate from other bodies
+/// Advances all bodies one fixed step under their mutual gravitational attraction.
+#[allow(clippy::too_many_arguments)]
 fn apply_gravity(
-    rapier_context: Res<RapierContext>,
+    time: Res<Time>,
+    clock: Res<SimulationClock>,
+    units: Res<SimulationUnits>,
+    gravity: Res<GravityConfig>,
+    solver: Res<GravitySolver>,
+    integrator: Res<Integrator>,
+    mut adaptive: ResMut<AdaptiveTimestep>,
     mut query: Query<(
         Entity,
-        &mut ExternalForce,
-        &Transform,
-        &ReadMassProperties,
+        &mut CelestialBody,
+        &mut Transform,
         &mut TwoBodyProblem,
+        Has<Pinned>,
     )>,
 ) {
-    // Scale to SI units for force calculations
-    let pixels_per_meter = rapier_context.integration_parameters.length_unit;
-    let gravitational_constant = 10.0;
-    let mut bodies = query.iter_combinations_mut();
-
-    while let Some(
-        [(entity1, mut force1, transform1, m1, mut two_body1), (entity2, mut force2, transform2, m2, mut two_body2)],
-    ) = bodies.fetch_next()
+    // Scale to simulation length units for force calculations
+    let pixels_per_length = units.pixels_per_length;
+
+    // Sort so the forces are always accumulated in the same order
+    let mut sorted: Vec<_> = query.iter_mut().collect();
+    sorted.sort_by_key(|(entity, ..)| *entity);
+
+    let entities: Vec<Entity> = sorted.iter().map(|(entity, ..)| *entity).collect();
+    let masses: Vec<f32> = sorted.iter().map(|(_, body, ..)| body.mass).collect();
+    let mut positions: Vec<Vec2> = sorted.iter().map(|(_, body, ..)| body.position).collect();
+    let mut velocities: Vec<Vec2> = sorted.iter().map(|(_, body, ..)| body.velocity).collect();
+    let pinned: Vec<usize> = (0..sorted.len()).filter(|&i| sorted[i].4).collect();
+
+    // A negative timestep runs the orbits backwards
+    let dt = clock.signed(time.delta_seconds());
+    let mut attractions = Vec::new();
+    // High warps take several whole timesteps per tick, rather than longer ones
+    for _ in 0..clock.steps_per_tick() {
+        adaptive.step(
+            *integrator,
+            &mut positions,
+            &mut velocities,
+            dt,
+            |positions| {
+                let (latest, accelerations) =
+                    solver.accelerations(positions, &masses, &gravity, pixels_per_length);
+                attractions = latest;
+                let partners = attractions
+                    .iter()
+                    .map(|attraction| attraction.dominant.map(|(other, _)| other))
+                    .collect();
+                (pin(accelerations, &pinned), partners)
+            },
+        );
+    }
+    let tick = dt * clock.steps_per_tick() as f32;
+
+    for (((_, body, transform, two_body, _), (position, velocity)), attraction) in sorted
+        .iter_mut()
+        .zip(positions.into_iter().zip(velocities))
+        .zip(attractions)
     {
-        let direction = (transform2.translation - transform1.translation) / pixels_per_meter; //FIXME: Get this scale from the physics config
-        let direction2 = Vec2::new(direction.x, direction.y);
-        let r2 = direction2.norm_squared();
-        let force = gravitational_constant * m1.mass * m2.mass / r2 * direction2.normalize();
-
-        if force.is_finite() {
-            force1.force += force;
-            force2.force += -force;
-
-            // If the force is most influential, store the two body problem
-            if two_body1.force.is_none() || two_body1.force.unwrap() < force.length() {
-                two_body1.update(entity2, force.length())
-            }
-            if two_body2.force.is_none() || two_body2.force.unwrap() < force.length() {
-                two_body2.update(entity1, force.length())
-            }
+        body.position = position;
+        body.velocity = velocity;
+        transform.translation = position.extend(transform.translation.z);
+        transform.rotate_z(body.spin * tick);
+
+        // Store the most influential body as the two body problem
+        **two_body = TwoBodyProblem::default();
+        if let Some((other, _)) = attraction.dominant {
+            two_body.update(entities[other]);
         }
     }
 }
 
+/// Zeroes the accelerations of the pinned bodies, which have no velocity, so they stay put.
+pub fn pin(mut accelerations: Vec<Vec2>, pinned: &[usize]) -> Vec<Vec2> {
+    for &index in pinned {
+        accelerations[index] = Vec2::ZERO;
+    }
+    accelerations
+}
+
+/// Switches between the exact and the Barnes-Hut gravity solvers, between integrators, and
+/// turns adaptive substepping on and off
+fn toggle_solvers(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut solver: ResMut<GravitySolver>,
+    mut gravity: ResMut<GravityConfig>,
+    mut integrator: ResMut<Integrator>,
+    mut adaptive: ResMut<AdaptiveTimestep>,
+) {
+    if keys.just_pressed(KeyCode::KeyG) {
+        *solver = match *solver {
+            GravitySolver::Pairwise => GravitySolver::default(),
+            GravitySolver::BarnesHut { .. } => GravitySolver::Pairwise,
+        };
+        info!("Gravity solver: {:?}", *solver);
+    }
+
+    if keys.just_pressed(KeyCode::KeyF) {
+        gravity.force_law = gravity.force_law.next();
+        info!("Force law: {:?}", gravity.force_law);
+    }
+
+    if keys.just_pressed(KeyCode::KeyI) {
+        *integrator = match *integrator {
+            Integrator::VelocityVerlet => Integrator::Yoshida4,
+            Integrator::Yoshida4 => Integrator::VelocityVerlet,
+        };
+        info!("Integrator: {:?}", *integrator);
+    }
+
+    if keys.just_pressed(KeyCode::KeyT) {
+        adaptive.enabled = !adaptive.enabled;
+        info!("Adaptive substepping: {}", adaptive.enabled);
+    }
+}
+
 use crate::Trail;
 
-/// Combines the momentum of two bodies that collide
+/// Resolves every pair of bodies that started touching this step, according to their
+/// `CollisionPolicy`
 // TODO: Only do this when they have a stable collision
-// TODO: Optional trail with Option<&Trail>
-pub fn combine_bodies(
+pub fn resolve_collisions(
     mut commands: Commands,
     mut collision_events: EventReader<CollisionEvent>,
     image_assets: Res<CelestialBodyAssets>,
-    query: Query<(&Transform, &Velocity, &ReadMassProperties, &Trail)>,
+    units: Res<SimulationUnits>,
+    gravity: Res<GravityConfig>,
+    global_policy: Res<CollisionPolicy>,
+    mut query: Query<(&mut CelestialBody, Option<&Trail>, Option<&CollisionPolicy>)>,
 ) {
-    for collision_event in collision_events.read() {
-        // Check for the correct collision event, otherwise skip
-        let (e1, e2, _) = match collision_event {
-            CollisionEvent::Started(e1, e2, flags) => (e1, e2, flags),
-            CollisionEvent::Stopped(..) => continue,
-        };
-
-        let properties = (query.get(*e1), query.get(*e2));
-        let (t1, v1, m1, trail1, t2, v2, m2, trail2) = match properties {
-            (Ok((t1, v1, m1, trail1)), Ok((t2, v2, m2, trail2))) => {
-                (t1, v1, m1, trail1, t2, v2, m2, trail2)
-            }
-            _ => continue,
-        };
+    // Check for the correct collision event, otherwise skip
+    let mut collisions: Vec<(Entity, Entity)> = collision_events
+        .read()
+        .filter_map(|collision_event| match collision_event {
+            CollisionEvent::Started(e1, e2, _) => Some((*e1.min(e2), *e1.max(e2))),
+            CollisionEvent::Stopped(..) => None,
+        })
+        .collect();
 
-        // Calculate combined mass and velocity
-        let mass1 = m1.mass;
-        let mass2 = m2.mass;
-        let combined_mass = mass1 + mass2;
+    // Resolve in a fixed order, and only touch each body once per step
+    collisions.sort();
+    collisions.dedup();
+    let mut resolved = Vec::new();
+    let pixels_per_length = units.pixels_per_length;
 
-        let combined_velocity = (v1.linvel * mass1 + v2.linvel * mass2) / combined_mass;
-        if !combined_velocity.is_finite() {
+    for (e1, e2) in &collisions {
+        if resolved.contains(e1) || resolved.contains(e2) {
             continue;
         }
 
-        // Calculate new center of mass
-        let combined_position =
-            (t1.translation.truncate() * mass1 + t2.translation.truncate() * mass2) / combined_mass;
+        let Ok([(mut b1, trail1, policy1), (mut b2, trail2, policy2)]) =
+            query.get_many_mut([*e1, *e2])
+        else {
+            continue;
+        };
 
-        if !combined_position.is_finite() {
+        // The heavier body's override wins
+        let (heavier_policy, lighter_policy) = if b1.mass > b2.mass {
+            (policy1, policy2)
+        } else {
+            (policy2, policy1)
+        };
+        let policy = heavier_policy.or(lighter_policy).unwrap_or(&global_policy);
+
+        let Some(outcome) = policy.resolve(&b1, &b2, &gravity, pixels_per_length) else {
             continue;
-        }
+        };
+        resolved.extend([*e1, *e2]);
 
-        // Spawn new combined rigid body
-        let entity = commands.spawn_empty().id();
-        add_sprite(&mut commands, entity, &image_assets, combined_mass);
-
-        add_celestial_body(
-            &mut commands,
-            entity,
-            CelestialBody::default()
-                .with_mass(combined_mass)
-                .with_position(combined_position)
-                .with_velocity(combined_velocity),
-        );
-        commands.entity(entity).insert(Trail::default());
+        let bodies = match outcome {
+            CollisionOutcome::Bounce(bounced1, bounced2) => {
+                *b1 = bounced1;
+                *b2 = bounced2;
+                continue;
+            }
+            CollisionOutcome::Merge(merged) => vec![merged],
+            CollisionOutcome::Fragment(fragments) => fragments,
+        };
+
+        // Overrides carry over from the heavier body
+        let inherited_policy = heavier_policy.copied();
+        let combined_position =
+            (b1.position * b1.mass + b2.position * b2.mass) / (b1.mass + b2.mass);
+        let heavier_is_first = b1.mass > b2.mass;
+
+        for body in bodies {
+            let mut entity = commands.spawn(CelestialBodyBundle::new(body, &image_assets));
+            entity.insert(Trail::default());
+            if let Some(policy) = inherited_policy {
+                entity.insert(policy);
+            }
+        }
 
         // Add a fading trail
         let fadout_time = 2f32;
-        let mut trail1 = trail1.with_fadeout(fadout_time);
-        let mut trail2 = trail2.with_fadeout(fadout_time);
+        let mut trail1 = trail1.map(|trail| trail.with_fadeout(fadout_time));
+        let mut trail2 = trail2.map(|trail| trail.with_fadeout(fadout_time));
 
-        if mass1 > mass2 {
-            trail1.add_vertex(combined_position);
+        let heavier_trail = if heavier_is_first {
+            trail1.as_mut()
         } else {
-            trail2.add_vertex(combined_position);
+            trail2.as_mut()
+        };
+        if let Some(trail) = heavier_trail {
+            trail.add_vertex(combined_position);
+        }
+        for trail in [trail1, trail2].into_iter().flatten() {
+            commands.spawn(trail);
         }
-        commands.spawn(trail1);
-        commands.spawn(trail2);
 
         // Despawn old entities
         commands.entity(*e1).despawn();
@@ -276,6 +541,19 @@ pub fn combine_bodies(
     }
 }
 
+/// Drops the collisions of steps that can't resolve them, so they aren't resolved later
+fn discard_collisions(mut collision_events: EventReader<CollisionEvent>) {
+    collision_events.clear();
+}
+
+/// Cycles the collision policy of the whole world when C is pressed
+fn cycle_collision_policy(keys: Res<ButtonInput<KeyCode>>, mut policy: ResMut<CollisionPolicy>) {
+    if keys.just_pressed(KeyCode::KeyC) {
+        *policy = policy.next();
+        info!("Collision policy: {:?}", *policy);
+    }
+}
+
 #[derive(Default, Resource)]
 struct MouseDragState {
     dragging: bool,
@@ -290,36 +568,97 @@ impl MouseDragState {
     }
 }
 
+/// Mass of bodies spawned with the mouse.
+const SPAWN_MASS: f32 = 1.0;
+
+/// Drags shorter than this spawn on a circular orbit.
+const ORBIT_DRAG_DEADZONE: f32 = 10.0;
+
+/// Drag length beyond the deadzone that would give a parabolic orbit.
+const ORBIT_DRAG_PER_ECCENTRICITY: f32 = 200.0;
+
+/// Places new bodies in orbit around the dominant body while shift is held.
+#[derive(SystemParam)]
+struct OrbitSpawn<'w, 's> {
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    units: Res<'w, SimulationUnits>,
+    gravity: Res<'w, GravityConfig>,
+    bodies: Query<'w, 's, &'static CelestialBody>,
+}
+
+/// A body's planned orbit around its primary.
+struct PlannedOrbit {
+    primary: Vec2,
+    conic: Option<Conic>,
+    velocity: Vec2,
+}
+
+impl OrbitSpawn<'_, '_> {
+    fn enabled(&self) -> bool {
+        self.keys
+            .any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight])
+    }
+
+    /// Plans an orbit starting at periapsis at `position`, for a drag released at `release`.
+    ///
+    /// The body orbits whatever pulls hardest at the release point, the drag length sets the
+    /// eccentricity and the drag direction picks which way round the body goes. Returns `None`
+    /// when there is nothing to orbit.
+    fn plan(&self, position: Vec2, release: Vec2, mass: f32) -> Option<PlannedOrbit> {
+        let primary = self
+            .bodies
+            .iter()
+            .filter(|body| body.position != position)
+            .max_by(|a, b| {
+                let pull =
+                    |body: &CelestialBody| body.mass / body.position.distance_squared(release);
+                pull(a).total_cmp(&pull(b))
+            })?;
+        let drag = position - release;
+
+        let pixels_per_length = self.units.pixels_per_length;
+        let mu = self
+            .gravity
+            .gravitational_parameter(mass + primary.mass, pixels_per_length)?;
+        let periapsis = position - primary.position;
+        let eccentricity =
+            (drag.length() - ORBIT_DRAG_DEADZONE).max(0.0) / ORBIT_DRAG_PER_ECCENTRICITY;
+        let retrograde = periapsis.perp_dot(drag) < 0.0;
+
+        let (_, velocity) =
+            OrbitalElements::from_periapsis(periapsis, eccentricity, retrograde, mu).to_state(mu);
+        Some(PlannedOrbit {
+            primary: primary.position,
+            conic: Conic::from_state(periapsis, velocity, mu),
+            velocity: primary.velocity + velocity,
+        })
+    }
+}
+
 // TODO: Split this into generating a non interacting sprite and then another system to convert it to a body
 fn spawn_on_mouse_drag(
     mut commands: Commands,
     mut drag_state: ResMut<MouseDragState>,
     mouse_button_input: Res<ButtonInput<MouseButton>>,
     celestial_body_assets: Res<CelestialBodyAssets>,
-    mut gizmos: Gizmos,
-    windows: Query<&Window>,
-    camera_q: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    cursor: WorldCursor,
+    orbit_spawn: OrbitSpawn,
+    picker: BodyPicker,
 ) {
-    // Get the primary window
-    let window = windows.single();
-
-    // Get the camera
-    let (camera, camera_transform) = camera_q.single();
-
-    let mass = 1.0;
-
-    // Check if the left mouse button is pressed
-    if mouse_button_input.just_pressed(MouseButton::Left) {
-        drag_state.dragging = true;
-        if let Some(world_position) = window
-            .cursor_position()
-            .and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor))
+    // Check if the left mouse button is pressed, ignoring clicks on the UI and on bodies, which
+    // select them instead
+    if mouse_button_input.just_pressed(MouseButton::Left) && !cursor.over_ui() {
+        if let Some(world_position) = cursor
+            .position()
+            .filter(|&position| picker.body_at(position).is_none())
         {
-            let entity = commands.spawn_empty().id();
-            add_sprite(&mut commands, entity, &celestial_body_assets, mass);
-            commands
-                .entity(entity)
-                .insert(Transform::from_translation(world_position.extend(0.0)));
+            drag_state.dragging = true;
+            let ghost = CelestialBody::default()
+                .with_mass(SPAWN_MASS)
+                .with_position(world_position);
+            let entity = commands
+                .spawn(celestial_body_assets.sprite_bundle(&ghost))
+                .id();
 
             // Store the data
             drag_state.initial_position = Some(world_position);
@@ -329,44 +668,32 @@ fn spawn_on_mouse_drag(
 
     // Get the current position based on mouse motion
     if drag_state.dragging {
-        if let Some(world_position) = window
-            .cursor_position()
-            .and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor))
-        {
+        if let Some(world_position) = cursor.position() {
             drag_state.current_position = Some(world_position);
         }
     }
 
-    // Draw a vector using gizmos if we have valid positions
-    if let (Some(initial_position), Some(current_position)) =
-        (drag_state.initial_position, drag_state.current_position)
-    {
-        let red = Color::srgb(1.0, 0.0, 0.0);
-        gizmos.linestrip_2d(vec![initial_position, current_position], red);
-    }
-
     if mouse_button_input.just_released(MouseButton::Left) {
         // Spawn the entity
         // Spawn a new entity at the cursor's world position
         // let mass = rng.gen_range(0.1..1.0) as f32;
-        let entity = commands.spawn_empty().id();
-
         if let (Some(inital_position), Some(current_position)) =
             (drag_state.initial_position, drag_state.current_position)
         {
             let velocity_scaled = inital_position - current_position;
-
-            add_sprite(&mut commands, entity, &celestial_body_assets, mass);
-            add_celestial_body(
-                &mut commands,
-                entity,
-                CelestialBody {
-                    position: inital_position,
-                    velocity: velocity_scaled,
-                    mass,
-                },
-            );
-            commands.entity(entity).insert(Trail::default());
+            let velocity = orbit_spawn
+                .enabled()
+                .then(|| orbit_spawn.plan(inital_position, current_position, SPAWN_MASS))
+                .flatten()
+                .map_or(velocity_scaled, |orbit| orbit.velocity);
+
+            let body = CelestialBody::default()
+                .with_mass(SPAWN_MASS)
+                .with_position(inital_position)
+                .with_velocity(velocity);
+            commands
+                .spawn(CelestialBodyBundle::new(body, &celestial_body_assets))
+                .insert(Trail::default());
         }
 
         // Remove the ghost
@@ -379,12 +706,47 @@ fn spawn_on_mouse_drag(
     }
 }
 
+/// Draws the drag vector, or the planned orbit while shift is held
+fn draw_spawn_preview(
+    mut gizmos: Gizmos,
+    drag_state: Res<MouseDragState>,
+    orbit_spawn: OrbitSpawn,
+) {
+    let (Some(initial_position), Some(current_position)) =
+        (drag_state.initial_position, drag_state.current_position)
+    else {
+        return;
+    };
+
+    let orbit = orbit_spawn
+        .enabled()
+        .then(|| orbit_spawn.plan(initial_position, current_position, SPAWN_MASS))
+        .flatten();
+    match orbit {
+        Some(PlannedOrbit {
+            primary,
+            conic: Some(conic),
+            ..
+        }) => {
+            let points = conic
+                .points(128, f32::INFINITY)
+                .into_iter()
+                .map(|point| primary + point);
+            gizmos.linestrip_2d(points, Color::srgb(0.5, 0.7, 1.0));
+        }
+        _ => {
+            let red = Color::srgb(1.0, 0.0, 0.0);
+            gizmos.linestrip_2d(vec![initial_position, current_position], red);
+        }
+    }
+}
+
 // Note this is inefficient as it will double up lines
 // TODO: Could potentially add the TwoBodyProblem as another entity ID but meh
 fn debug_draw_two_body_connection(
     world: &World,
     mut gizmos: Gizmos,
-    query: Query<(&Transform, &TwoBodyProblem)>,
+    query: Query<(&Transform, &TwoBodyProblem), With<CelestialBody>>,
 ) {
     for (transform, two_body_problem) in &query {
         if let Some(other_entity) = two_body_problem.entity {
@@ -0,0 +1,392 @@
+use bevy::prelude::*;
+use std::time::Duration;
+
+pub struct ClockPlugin {
+    /// Skips the keyboard and UI controls, for apps without a window.
+    pub headless: bool,
+}
+
+impl Plugin for ClockPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimulationClock>()
+            .add_systems(FixedLast, advance_clock);
+
+        if self.headless {
+            return;
+        }
+        app.add_systems(Startup, setup_clock_controls).add_systems(
+            Update,
+            (
+                (clock_keys, clock_buttons),
+                apply_warp,
+                update_clock_buttons,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// The warp factors stepped through by the faster and slower controls.
+const WARP_STEPS: [f32; 13] = [
+    0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0,
+];
+
+/// Most fixed steps to catch up on in one frame, so high warps can't stall the app.
+///
+/// With the fixed loop running at most `MAX_TICK_WARP` times the wall clock, this only holds the
+/// warp back below about 25 FPS with the default timestep, which the overlay then shows.
+const MAX_STEPS_PER_FRAME: f64 = 256.0;
+
+/// Fastest the fixed loop runs. Higher warps integrate several timesteps on each tick instead.
+const MAX_TICK_WARP: f32 = 100.0;
+
+/// Controls how simulated time advances.
+///
+/// Running backwards integrates with a negative timestep, which retraces the orbits because the
+/// integrators are time-reversible. Merges can't be undone, so collisions are ignored meanwhile.
+#[derive(Resource, Clone, Debug)]
+pub struct SimulationClock {
+    pub paused: bool,
+    pub reversed: bool,
+    /// Multiple of wall clock time, one of `WARP_STEPS`.
+    warp: f32,
+    /// Run one fixed step while paused.
+    single_step: bool,
+    /// Simulated seconds since startup, which go down while reversed.
+    elapsed: f64,
+}
+
+impl Default for SimulationClock {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            reversed: false,
+            warp: 1.0,
+            single_step: false,
+            elapsed: 0.0,
+        }
+    }
+}
+
+impl SimulationClock {
+    pub fn warp(&self) -> f32 {
+        self.warp
+    }
+
+    pub fn faster(&mut self) {
+        if let Some(&warp) = WARP_STEPS.iter().find(|&&warp| warp > self.warp) {
+            self.warp = warp;
+        }
+    }
+
+    pub fn slower(&mut self) {
+        if let Some(&warp) = WARP_STEPS.iter().rev().find(|&&warp| warp < self.warp) {
+            self.warp = warp;
+        }
+    }
+
+    /// Timesteps integrated on each fixed tick, so the loop itself stays under `MAX_TICK_WARP`.
+    ///
+    /// Stepping while paused always takes a single timestep.
+    pub fn steps_per_tick(&self) -> u32 {
+        if self.paused {
+            1
+        } else {
+            self.steps_at_warp()
+        }
+    }
+
+    fn steps_at_warp(&self) -> u32 {
+        (self.warp / MAX_TICK_WARP).ceil().max(1.0) as u32
+    }
+
+    /// How fast the fixed loop runs, paused or not.
+    fn tick_warp(&self) -> f32 {
+        self.warp / self.steps_at_warp() as f32
+    }
+
+    /// Advances one fixed step on the next tick, if paused.
+    pub fn step_once(&mut self) {
+        self.single_step = self.paused;
+    }
+
+    /// Whether bodies should move on this fixed step.
+    pub fn is_running(&self) -> bool {
+        !self.paused || self.single_step
+    }
+
+    /// Whether this fixed step moves time forwards, so collisions can be resolved.
+    pub fn is_running_forwards(&self) -> bool {
+        self.is_running() && !self.reversed
+    }
+
+    /// Scales a timestep by the direction of time.
+    pub fn signed(&self, dt: f32) -> f32 {
+        if self.reversed {
+            -dt
+        } else {
+            dt
+        }
+    }
+
+    pub fn elapsed_seconds(&self) -> f64 {
+        self.elapsed
+    }
+}
+
+/// Run condition for the systems that move bodies.
+pub fn clock_is_running(clock: Res<SimulationClock>) -> bool {
+    clock.is_running()
+}
+
+/// Run condition for the systems that only make sense going forwards.
+pub fn clock_is_running_forwards(clock: Res<SimulationClock>) -> bool {
+    clock.is_running_forwards()
+}
+
+pub fn advance_clock(mut clock: ResMut<SimulationClock>, time: Res<Time<Fixed>>) {
+    if !clock.is_running() {
+        return;
+    }
+    let dt = clock.signed(time.delta_seconds()) as f64 * clock.steps_per_tick() as f64;
+    clock.elapsed += dt;
+    clock.single_step = false;
+}
+
+/// Speeds up virtual time, which drives the fixed loop
+fn apply_warp(
+    clock: Res<SimulationClock>,
+    fixed_time: Res<Time<Fixed>>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+) {
+    if !clock.is_changed() {
+        return;
+    }
+
+    // The rest of the warp comes from the steps taken on each tick
+    let warp = clock.tick_warp() as f64;
+    // Cap the backlog so a slow frame doesn't lead to an even slower one
+    let max_delta = (MAX_STEPS_PER_FRAME * fixed_time.timestep().as_secs_f64() / warp).min(0.25);
+    virtual_time.set_max_delta(Duration::from_secs_f64(max_delta));
+    virtual_time.set_relative_speed_f64(warp);
+}
+
+/// The actions available from both the keyboard and the on-screen buttons.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+enum ClockControl {
+    Pause,
+    Step,
+    Slower,
+    Faster,
+    Reverse,
+}
+
+impl ClockControl {
+    const ALL: [Self; 5] = [
+        Self::Reverse,
+        Self::Slower,
+        Self::Pause,
+        Self::Step,
+        Self::Faster,
+    ];
+
+    fn key(self) -> KeyCode {
+        match self {
+            Self::Pause => KeyCode::Space,
+            Self::Step => KeyCode::Period,
+            Self::Slower => KeyCode::Minus,
+            Self::Faster => KeyCode::Equal,
+            Self::Reverse => KeyCode::KeyR,
+        }
+    }
+
+    fn label(self, clock: &SimulationClock) -> &'static str {
+        match self {
+            Self::Pause if clock.paused => "Play",
+            Self::Pause => "Pause",
+            Self::Step => "Step",
+            Self::Slower => "Slower",
+            Self::Faster => "Faster",
+            Self::Reverse if clock.reversed => "Forwards",
+            Self::Reverse => "Reverse",
+        }
+    }
+
+    fn apply(self, clock: &mut SimulationClock) {
+        match self {
+            Self::Pause => clock.paused = !clock.paused,
+            Self::Step => clock.step_once(),
+            Self::Slower => clock.slower(),
+            Self::Faster => clock.faster(),
+            Self::Reverse => clock.reversed = !clock.reversed,
+        }
+    }
+}
+
+fn clock_keys(keys: Res<ButtonInput<KeyCode>>, mut clock: ResMut<SimulationClock>) {
+    for control in ClockControl::ALL {
+        if keys.just_pressed(control.key()) {
+            control.apply(&mut clock);
+        }
+    }
+}
+
+fn clock_buttons(
+    query: Query<(&Interaction, &ClockControl), Changed<Interaction>>,
+    mut clock: ResMut<SimulationClock>,
+) {
+    for (interaction, control) in &query {
+        if *interaction == Interaction::Pressed {
+            control.apply(&mut clock);
+        }
+    }
+}
+
+fn setup_clock_controls(mut commands: Commands) {
+    let clock = SimulationClock::default();
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(5.0),
+                left: Val::Px(5.0),
+                column_gap: Val::Px(5.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            for control in ClockControl::ALL {
+                parent
+                    .spawn(ButtonBundle {
+                        style: Style {
+                            padding: UiRect::axes(Val::Px(8.0), Val::Px(4.0)),
+                            ..Default::default()
+                        },
+                        background_color: Color::srgba(0.2, 0.2, 0.2, 0.8).into(),
+                        ..Default::default()
+                    })
+                    .insert(control)
+                    .with_children(|button| {
+                        button.spawn(TextBundle::from_section(
+                            control.label(&clock),
+                            TextStyle {
+                                font_size: 20.0,
+                                color: Color::WHITE,
+                                ..default()
+                            },
+                        ));
+                    });
+            }
+        });
+}
+
+/// Keeps the toggle labels in sync with the clock
+fn update_clock_buttons(
+    clock: Res<SimulationClock>,
+    buttons: Query<(&ClockControl, &Children)>,
+    mut texts: Query<&mut Text>,
+) {
+    if !clock.is_changed() {
+        return;
+    }
+    for (control, children) in &buttons {
+        for &child in children {
+            if let Ok(mut text) = texts.get_mut(child) {
+                text.sections[0].value = control.label(&clock).to_string();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::celestial_body::CelestialBody;
+    use crate::headless::headless_app;
+    use crate::scenario::{BodyDescription, Scenario};
+
+    #[test]
+    fn warp_steps_stay_in_range() {
+        let mut clock = SimulationClock::default();
+        for _ in 0..20 {
+            clock.faster();
+        }
+        assert_eq!(clock.warp(), 1000.0);
+        for _ in 0..20 {
+            clock.slower();
+        }
+        assert_eq!(clock.warp(), 0.1);
+    }
+
+    #[test]
+    fn high_warps_take_several_steps_per_tick() {
+        let mut clock = SimulationClock::default();
+        for &warp in &WARP_STEPS {
+            clock.warp = warp;
+            let tick_warp = clock.tick_warp();
+            assert!(tick_warp <= MAX_TICK_WARP, "{warp}x ticks at {tick_warp}x");
+            let steps = clock.steps_per_tick() as f32;
+            assert!((tick_warp * steps - warp).abs() <= 1.0e-4 * warp);
+        }
+        assert_eq!(clock.steps_per_tick(), 10);
+
+        clock.paused = true;
+        assert_eq!(clock.steps_per_tick(), 1);
+    }
+
+    #[test]
+    fn single_step_only_while_paused() {
+        let mut clock = SimulationClock::default();
+        clock.step_once();
+        assert!(!clock.single_step);
+
+        clock.paused = true;
+        assert!(!clock.is_running());
+        clock.step_once();
+        assert!(clock.is_running());
+    }
+
+    #[test]
+    fn fast_ticks_match_the_same_number_of_timesteps() {
+        let bodies = [
+            CelestialBody::default().with_mass(10.0),
+            CelestialBody::default()
+                .with_position(Vec2::new(150.0, 0.0))
+                .with_velocity(Vec2::new(0.0, 90.0)),
+        ];
+        let scenario = Scenario {
+            bodies: bodies
+                .iter()
+                .map(|body| BodyDescription::new(body, &default()))
+                .collect(),
+            ..default()
+        };
+
+        // Ten ticks at the real warp, and one tick at the highest
+        let run = |ticks: u32, warp: f32| {
+            let mut app = headless_app(scenario.clone());
+            app.update();
+            while app.world().resource::<SimulationClock>().warp() < warp {
+                app.world_mut().resource_mut::<SimulationClock>().faster();
+            }
+            for _ in 0..ticks {
+                app.update();
+            }
+            let world = app.world_mut();
+            let elapsed = world.resource::<SimulationClock>().elapsed_seconds();
+            let mut positions: Vec<(Entity, Vec2)> = world
+                .query::<(Entity, &CelestialBody)>()
+                .iter(world)
+                .map(|(entity, body)| (entity, body.position))
+                .collect();
+            positions.sort_by_key(|(entity, _)| *entity);
+            (elapsed, positions)
+        };
+        let (slow_elapsed, slow) = run(10, 1.0);
+        let (fast_elapsed, fast) = run(1, 1000.0);
+
+        assert!((slow_elapsed - fast_elapsed).abs() < 1.0e-6);
+        assert_eq!(slow, fast);
+    }
+}
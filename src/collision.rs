@@ -0,0 +1,388 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+use crate::celestial_body::CelestialBody;
+use crate::gravity::GravityConfig;
+
+/// Decides what happens when two bodies touch.
+///
+/// Used as a resource for the whole world, and as a component to override it for a single body.
+/// When both bodies carry an override, the heavier one wins.
+#[derive(Resource, Component, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum CollisionPolicy {
+    /// Perfectly inelastic, the bodies always merge.
+    #[default]
+    Merge,
+    /// The bodies bounce off each other, keeping `restitution` of their closing speed.
+    Bounce { restitution: f32 },
+    /// Merge when the bodies are bound to each other at contact, otherwise bounce.
+    MergeBelowEscapeVelocity { restitution: f32 },
+    /// Shatter into `fragments` equal bodies, or merge if they would be lighter than `min_mass`.
+    ///
+    /// The fragments fly apart with `restitution` of the kinetic energy of the impact.
+    Fragment {
+        fragments: u32,
+        min_mass: f32,
+        restitution: f32,
+    },
+}
+
+/// The bodies that replace a colliding pair.
+#[derive(Clone, Debug)]
+pub enum CollisionOutcome {
+    Merge(CelestialBody),
+    /// The two bodies with new positions and velocities, in the order they were given.
+    Bounce(CelestialBody, CelestialBody),
+    Fragment(Vec<CelestialBody>),
+}
+
+impl CollisionPolicy {
+    /// The policy after this one, for cycling through them from the keyboard.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Merge => Self::Bounce { restitution: 0.8 },
+            Self::Bounce { .. } => Self::MergeBelowEscapeVelocity { restitution: 0.5 },
+            Self::MergeBelowEscapeVelocity { .. } => Self::Fragment {
+                fragments: 4,
+                min_mass: 0.2,
+                restitution: 0.5,
+            },
+            Self::Fragment { .. } => Self::Merge,
+        }
+    }
+
+    /// Works out what two touching bodies turn into.
+    ///
+    /// Mass and linear momentum are always conserved. Returns `None` if the result is not finite.
+    pub fn resolve(
+        &self,
+        b1: &CelestialBody,
+        b2: &CelestialBody,
+        gravity: &GravityConfig,
+        pixels_per_length: f32,
+    ) -> Option<CollisionOutcome> {
+        match *self {
+            Self::Merge => merge(b1, b2),
+            Self::Bounce { restitution } => bounce(b1, b2, restitution),
+            Self::MergeBelowEscapeVelocity { restitution } => {
+                // Relative kinetic energy needed to climb out of the potential well
+                let reduced_mass = b1.mass * b2.mass / (b1.mass + b2.mass);
+                let contact = (b1.radius() + b2.radius()) / pixels_per_length;
+                let well =
+                    -pixels_per_length.powi(2) * gravity.potential(b1.mass, b2.mass, contact);
+                let escape_speed = (2.0 * well / reduced_mass).max(0.0).sqrt();
+                if b1.velocity.distance(b2.velocity) < escape_speed {
+                    merge(b1, b2)
+                } else {
+                    bounce(b1, b2, restitution)
+                }
+            }
+            Self::Fragment {
+                fragments,
+                min_mass,
+                restitution,
+            } => {
+                let mass = (b1.mass + b2.mass) / fragments as f32;
+                if fragments < 2 || mass < min_mass {
+                    merge(b1, b2)
+                } else {
+                    fragment(b1, b2, fragments, restitution)
+                }
+            }
+        }
+    }
+}
+
+/// Combines the bodies at their centre of mass, named after the heavier of the two.
+///
+/// The spins and the orbital angular momentum of the pair end up as the spin of the new body.
+fn merge(b1: &CelestialBody, b2: &CelestialBody) -> Option<CollisionOutcome> {
+    let combined_mass = b1.mass + b2.mass;
+    let combined_velocity = (b1.velocity * b1.mass + b2.velocity * b2.mass) / combined_mass;
+    let combined_position = (b1.position * b1.mass + b2.position * b2.mass) / combined_mass;
+    if !combined_velocity.is_finite() || !combined_position.is_finite() {
+        return None;
+    }
+
+    let angular_momentum = b1.angular_momentum(combined_position, combined_velocity)
+        + b2.angular_momentum(combined_position, combined_velocity);
+
+    // The heavier body keeps its name, and its kind even when that was given rather than derived
+    let heavier = if b1.mass > b2.mass { b1 } else { b2 };
+    let merged = CelestialBody {
+        kind: heavier.kind,
+        ..heavier
+            .with_mass(combined_mass)
+            .with_position(combined_position)
+            .with_velocity(combined_velocity)
+    };
+    let spin = angular_momentum / merged.moment_of_inertia();
+    Some(CollisionOutcome::Merge(merged.with_spin(spin)))
+}
+
+/// Applies an impulse along the line between the centres and pushes the bodies apart.
+fn bounce(b1: &CelestialBody, b2: &CelestialBody, restitution: f32) -> Option<CollisionOutcome> {
+    let normal = (b2.position - b1.position).try_normalize()?;
+    let inverse_mass1 = 1.0 / b1.mass;
+    let inverse_mass2 = 1.0 / b2.mass;
+
+    // Only bounce bodies that are closing, otherwise they are already separating
+    let closing_speed = (b2.velocity - b1.velocity).dot(normal);
+    let impulse = (-(1.0 + restitution) * closing_speed).max(0.0) / (inverse_mass1 + inverse_mass2);
+
+    // Move out of contact without shifting the centre of mass
+    let overlap = (b1.radius() + b2.radius() - b1.position.distance(b2.position)).max(0.0);
+    let correction = overlap / (inverse_mass1 + inverse_mass2);
+
+    let bounced1 = b1
+        .with_position(b1.position - correction * inverse_mass1 * normal)
+        .with_velocity(b1.velocity - impulse * inverse_mass1 * normal);
+    let bounced2 = b2
+        .with_position(b2.position + correction * inverse_mass2 * normal)
+        .with_velocity(b2.velocity + impulse * inverse_mass2 * normal);
+    Some(CollisionOutcome::Bounce(bounced1, bounced2))
+}
+
+/// Splits the combined mass into equal fragments spread evenly around the centre of mass.
+///
+/// The fragments are placed and launched symmetrically, so their momenta cancel about the
+/// combined velocity. The angular momentum of the merged body is kept by spinning the whole
+/// cloud of fragments as if it were rigid.
+fn fragment(
+    b1: &CelestialBody,
+    b2: &CelestialBody,
+    fragments: u32,
+    restitution: f32,
+) -> Option<CollisionOutcome> {
+    let CollisionOutcome::Merge(merged) = merge(b1, b2)? else {
+        return None;
+    };
+    let mass = merged.mass / fragments as f32;
+    let template = CelestialBody {
+        kind: merged.kind,
+        ..merged.with_mass(mass)
+    };
+
+    // Far enough apart that neighbouring fragments don't touch
+    let spacing = 1.05 * template.radius() / (PI / fragments as f32).sin();
+
+    // Share out the requested fraction of the kinetic energy of the impact
+    let reduced_mass = b1.mass * b2.mass / merged.mass;
+    let impact_energy = 0.5 * reduced_mass * b1.velocity.distance_squared(b2.velocity);
+    let speed = (2.0 * restitution * impact_energy / merged.mass).sqrt();
+
+    let cloud_inertia =
+        merged.mass * spacing.powi(2) + fragments as f32 * template.moment_of_inertia();
+    let spin = merged.moment_of_inertia() * merged.spin / cloud_inertia;
+
+    let offset = (b2.position - b1.position).to_angle();
+    let bodies = (0..fragments)
+        .map(|i| {
+            let direction = Vec2::from_angle(offset + 2.0 * PI * i as f32 / fragments as f32);
+            CelestialBody {
+                name: format!("{} fragment", merged.name.trim_end_matches(" fragment")),
+                ..template
+                    .with_position(merged.position + spacing * direction)
+                    .with_velocity(
+                        merged.velocity + speed * direction + spin * spacing * direction.perp(),
+                    )
+                    .with_spin(spin)
+            }
+        })
+        .collect();
+    Some(CollisionOutcome::Fragment(bodies))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::celestial_body::CelestialBodyKind;
+
+    fn bodies() -> (CelestialBody, CelestialBody) {
+        (
+            CelestialBody::default()
+                .with_mass(3.0)
+                .with_position(Vec2::new(-5.0, 1.0))
+                .with_velocity(Vec2::new(40.0, -10.0))
+                .with_spin(0.5),
+            CelestialBody::default()
+                .with_mass(1.5)
+                .with_position(Vec2::new(5.0, 0.0))
+                .with_velocity(Vec2::new(-60.0, 20.0))
+                .with_spin(-2.0),
+        )
+    }
+
+    fn outcome_bodies(outcome: CollisionOutcome) -> Vec<CelestialBody> {
+        match outcome {
+            CollisionOutcome::Merge(body) => vec![body],
+            CollisionOutcome::Bounce(b1, b2) => vec![b1, b2],
+            CollisionOutcome::Fragment(bodies) => bodies,
+        }
+    }
+
+    fn assert_conserved(policy: CollisionPolicy, gravity: &GravityConfig) -> Vec<CelestialBody> {
+        let (b1, b2) = bodies();
+        let outcome = policy.resolve(&b1, &b2, gravity, 100.0).unwrap();
+        let after = outcome_bodies(outcome);
+
+        let mass: f32 = after.iter().map(|body| body.mass).sum();
+        let momentum: Vec2 = after.iter().map(|body| body.mass * body.velocity).sum();
+        let moment: Vec2 = after.iter().map(|body| body.mass * body.position).sum();
+
+        assert!((mass - (b1.mass + b2.mass)).abs() < 1.0e-5);
+        let expected_momentum = b1.mass * b1.velocity + b2.mass * b2.velocity;
+        assert!(
+            momentum.distance(expected_momentum) < 1.0e-3,
+            "{momentum} != {expected_momentum}"
+        );
+        let expected_moment = b1.mass * b1.position + b2.mass * b2.position;
+        assert!(moment.distance(expected_moment) < 1.0e-3);
+        after
+    }
+
+    /// Total spin and orbital angular momentum about the origin.
+    fn angular_momentum(bodies: &[CelestialBody]) -> f32 {
+        bodies
+            .iter()
+            .map(|body| body.angular_momentum(Vec2::ZERO, Vec2::ZERO))
+            .sum()
+    }
+
+    #[test]
+    fn merges_conserve_angular_momentum() {
+        let (b1, b2) = bodies();
+        let before = angular_momentum(&[b1.clone(), b2.clone()]);
+
+        for policy in [
+            CollisionPolicy::Merge,
+            CollisionPolicy::Fragment {
+                fragments: 3,
+                min_mass: 0.1,
+                restitution: 0.5,
+            },
+        ] {
+            let after = angular_momentum(&assert_conserved(policy, &GravityConfig::default()));
+            assert!(
+                (after - before).abs() < 1.0e-4 * before.abs(),
+                "{policy:?}: {after} != {before}"
+            );
+        }
+    }
+
+    #[test]
+    fn merged_body_spins_with_the_orbit() {
+        // Two bodies grazing anticlockwise past each other, with no spin of their own
+        let (b1, b2) = bodies();
+        let b1 = b1.with_spin(0.0).with_velocity(Vec2::new(0.0, -10.0));
+        let b2 = b2.with_spin(0.0).with_velocity(Vec2::new(0.0, 10.0));
+        let CollisionOutcome::Merge(merged) = CollisionPolicy::Merge
+            .resolve(&b1, &b2, &GravityConfig::default(), 100.0)
+            .unwrap()
+        else {
+            panic!("expected a merge");
+        };
+        assert!(merged.spin > 0.0);
+    }
+
+    #[test]
+    fn merge_conserves_mass_and_momentum() {
+        let after = assert_conserved(CollisionPolicy::Merge, &GravityConfig::default());
+        assert_eq!(after.len(), 1);
+    }
+
+    #[test]
+    fn bounce_conserves_mass_and_momentum() {
+        let (b1, b2) = bodies();
+        let after = assert_conserved(
+            CollisionPolicy::Bounce { restitution: 1.0 },
+            &GravityConfig::default(),
+        );
+        assert_eq!(after.len(), 2);
+
+        // A perfectly elastic bounce also keeps the kinetic energy
+        let energy = |bodies: &[&CelestialBody]| -> f32 {
+            bodies
+                .iter()
+                .map(|body| 0.5 * body.mass * body.velocity.length_squared())
+                .sum()
+        };
+        let before = energy(&[&b1, &b2]);
+        assert!((energy(&[&after[0], &after[1]]) - before).abs() < 1.0e-3 * before);
+        assert!(
+            (after[1].velocity - after[0].velocity).dot(after[1].position - after[0].position)
+                > 0.0
+        );
+    }
+
+    #[test]
+    fn merge_below_escape_velocity_conserves_mass_and_momentum() {
+        // Weak gravity can't hold the bodies together, strong gravity can
+        let policy = CollisionPolicy::MergeBelowEscapeVelocity { restitution: 0.5 };
+        let weak = GravityConfig {
+            gravitational_constant: 1.0e-5,
+            ..default()
+        };
+        assert_eq!(assert_conserved(policy, &weak).len(), 2);
+        assert_eq!(assert_conserved(policy, &GravityConfig::default()).len(), 1);
+    }
+
+    #[test]
+    fn fragmentation_conserves_mass_and_momentum() {
+        let after = assert_conserved(
+            CollisionPolicy::Fragment {
+                fragments: 5,
+                min_mass: 0.1,
+                restitution: 0.5,
+            },
+            &GravityConfig::default(),
+        );
+        assert_eq!(after.len(), 5);
+
+        // No two fragments start out touching
+        for (i, a) in after.iter().enumerate() {
+            for b in &after[i + 1..] {
+                assert!(a.position.distance(b.position) > a.radius() + b.radius());
+            }
+        }
+    }
+
+    #[test]
+    fn outcomes_keep_the_kind_of_the_heavier_body() {
+        let (b1, b2) = bodies();
+        let b1 = CelestialBody {
+            kind: CelestialBodyKind::Moon,
+            ..b1
+        };
+        let policies = [
+            CollisionPolicy::Merge,
+            CollisionPolicy::Fragment {
+                fragments: 3,
+                min_mass: 0.1,
+                restitution: 0.5,
+            },
+        ];
+        for policy in policies {
+            let outcome = policy
+                .resolve(&b1, &b2, &GravityConfig::default(), 100.0)
+                .unwrap();
+            for body in outcome_bodies(outcome) {
+                assert_eq!(body.kind, CelestialBodyKind::Moon, "{policy:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn small_fragments_merge_instead() {
+        let after = assert_conserved(
+            CollisionPolicy::Fragment {
+                fragments: 5,
+                min_mass: 1.0,
+                restitution: 0.5,
+            },
+            &GravityConfig::default(),
+        );
+        assert_eq!(after.len(), 1);
+    }
+}
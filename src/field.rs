@@ -0,0 +1,494 @@
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+use crate::camera::ease_camera;
+use crate::celestial_body::CelestialBody;
+use crate::gravity::GravityConfig;
+use crate::units::SimulationUnits;
+use crate::MainCamera;
+
+pub struct FieldPlugin;
+impl Plugin for FieldPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FieldSettings>()
+            .add_systems(Startup, setup_field_layer)
+            .add_systems(
+                Update,
+                // Drawn over the viewport the camera ends the frame on
+                (field_keys, update_field_layer.after(ease_camera)).chain(),
+            );
+    }
+}
+
+/// What the background heatmap shows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FieldLayer {
+    #[default]
+    Off,
+    /// Potential energy per unit mass, deepest in the wells around the bodies.
+    Potential,
+    /// Magnitude of the gravitational acceleration.
+    Strength,
+}
+
+impl FieldLayer {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Potential,
+            Self::Potential => Self::Strength,
+            Self::Strength => Self::Off,
+        }
+    }
+}
+
+/// How the background heatmap is sampled and coloured.
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct FieldSettings {
+    pub layer: FieldLayer,
+    /// Samples across the width of the viewport.
+    pub resolution: u32,
+    /// Multiplies the value that maps to the middle of the colour scale, which is otherwise the
+    /// median of the samples in view.
+    pub colour_scale: f32,
+}
+
+impl Default for FieldSettings {
+    fn default() -> Self {
+        Self {
+            layer: FieldLayer::default(),
+            resolution: 96,
+            colour_scale: 1.0,
+        }
+    }
+}
+
+const MIN_RESOLUTION: u32 = 8;
+const MAX_RESOLUTION: u32 = 512;
+
+/// A grid of sample points covering a rectangle that may be turned, such as the camera viewport.
+///
+/// Rows run from the top of the rectangle down, to match the rows of an image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FieldGrid {
+    pub centre: Vec2,
+    pub size: Vec2,
+    /// Unit vector along the rectangle's width.
+    pub axis: Vec2,
+    pub columns: u32,
+    pub rows: u32,
+}
+
+impl FieldGrid {
+    /// The centre of each cell, row by row.
+    pub fn points(&self) -> impl Iterator<Item = Vec2> + '_ {
+        let cell = self.size / Vec2::new(self.columns as f32, self.rows as f32);
+        (0..self.rows).flat_map(move |row| {
+            (0..self.columns).map(move |column| {
+                let local = Vec2::new(
+                    (column as f32 + 0.5) * cell.x - self.size.x / 2.0,
+                    self.size.y / 2.0 - (row as f32 + 0.5) * cell.y,
+                );
+                self.centre + self.axis.rotate(local)
+            })
+        })
+    }
+}
+
+/// Samples the field of point masses, given in pixels, at each point.
+///
+/// Uses the same force law and pixel scaling as `apply_gravity`, so potentials are in pixels² per
+/// time unit² and strengths in pixels per time unit².
+pub fn sample_field(
+    layer: FieldLayer,
+    bodies: &[(Vec2, f32)],
+    gravity: &GravityConfig,
+    pixels_per_length: f32,
+    points: impl Iterator<Item = Vec2>,
+) -> Vec<f32> {
+    points
+        .map(|point| match layer {
+            FieldLayer::Off => 0.0,
+            FieldLayer::Potential => bodies
+                .iter()
+                .map(|&(position, mass)| {
+                    let distance = point.distance(position) / pixels_per_length;
+                    pixels_per_length.powi(2) * gravity.potential(1.0, mass, distance)
+                })
+                .sum(),
+            FieldLayer::Strength => {
+                let field: Vec2 = bodies
+                    .iter()
+                    .map(|&(position, mass)| {
+                        let offset = (position - point) / pixels_per_length;
+                        gravity.force(1.0, mass, offset) * pixels_per_length
+                    })
+                    .sum();
+                field.length()
+            }
+        })
+        .collect()
+}
+
+/// Stops of the colour scale, from the shallowest to the deepest part of the field.
+const COLOUR_STOPS: [[f32; 3]; 5] = [
+    [0.0, 0.0, 0.02],
+    [0.34, 0.06, 0.43],
+    [0.74, 0.22, 0.33],
+    [0.98, 0.56, 0.04],
+    [0.99, 1.0, 0.64],
+];
+
+/// Looks up `t` in `[0, 1]` on the colour scale.
+fn colour_at(t: f32) -> [f32; 3] {
+    let position = t.clamp(0.0, 1.0) * (COLOUR_STOPS.len() - 1) as f32;
+    let index = (position as usize).min(COLOUR_STOPS.len() - 2);
+    let fraction = position - index as f32;
+    let (from, to) = (COLOUR_STOPS[index], COLOUR_STOPS[index + 1]);
+    [0, 1, 2].map(|channel| from[channel] + (to[channel] - from[channel]) * fraction)
+}
+
+/// Colours the samples as RGBA pixels.
+///
+/// Potentials are measured down from the highest sample, so any force law has a zero level.
+/// Values then map through `v / (v + reference)`, where the reference is the median scaled by
+/// `colour_scale`, so the picture keeps its contrast at any zoom and in any units.
+pub fn colour_field(layer: FieldLayer, values: &[f32], colour_scale: f32) -> Vec<u8> {
+    let top = values
+        .iter()
+        .copied()
+        .filter(|value| value.is_finite())
+        .fold(f32::NEG_INFINITY, f32::max);
+    let depths: Vec<f32> = values
+        .iter()
+        .map(|&value| match layer {
+            FieldLayer::Potential => top - value,
+            FieldLayer::Off | FieldLayer::Strength => value,
+        })
+        .map(|depth| if depth.is_nan() { 0.0 } else { depth })
+        .collect();
+
+    let mut sorted: Vec<f32> = depths.iter().copied().filter(|d| d.is_finite()).collect();
+    sorted.sort_by(f32::total_cmp);
+    let median = sorted.get(sorted.len() / 2).copied().unwrap_or(0.0);
+    let reference = (median * colour_scale).max(f32::MIN_POSITIVE);
+
+    depths
+        .iter()
+        .flat_map(|&depth| {
+            let t = if depth.is_finite() {
+                depth / (depth + reference)
+            } else {
+                1.0
+            };
+            let [r, g, b] = colour_at(t).map(|channel| (channel * 255.0).round() as u8);
+            [r, g, b, 255]
+        })
+        .collect()
+}
+
+/// Marks the sprite the heatmap is drawn on.
+#[derive(Component)]
+struct FieldSprite;
+
+/// Depth of the heatmap, behind the bodies at zero and in front of the camera's far plane.
+const FIELD_DEPTH: f32 = -0.05;
+
+fn setup_field_layer(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let image = Image::new_fill(
+        Extent3d::default(),
+        TextureDimension::D2,
+        &[0, 0, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    commands
+        .spawn(SpriteBundle {
+            texture: images.add(image),
+            visibility: Visibility::Hidden,
+            ..Default::default()
+        })
+        .insert(FieldSprite);
+}
+
+/// Cycles the heatmap with B, changes its resolution with `[` and `]`, and its colour scale with
+/// `;` and `'`
+fn field_keys(keys: Res<ButtonInput<KeyCode>>, mut settings: ResMut<FieldSettings>) {
+    if keys.just_pressed(KeyCode::KeyB) {
+        settings.layer = settings.layer.next();
+        info!("Field layer: {:?}", settings.layer);
+    }
+    if keys.just_pressed(KeyCode::BracketLeft) {
+        settings.resolution = (settings.resolution / 2).max(MIN_RESOLUTION);
+    }
+    if keys.just_pressed(KeyCode::BracketRight) {
+        settings.resolution = (settings.resolution * 2).min(MAX_RESOLUTION);
+    }
+    if keys.just_pressed(KeyCode::Semicolon) {
+        settings.colour_scale /= 2.0;
+    }
+    if keys.just_pressed(KeyCode::Quote) {
+        settings.colour_scale *= 2.0;
+    }
+}
+
+/// The components of the heatmap sprite that are redrawn.
+type FieldSpriteParts<'a> = (
+    &'a mut Sprite,
+    &'a mut Transform,
+    &'a mut Visibility,
+    &'a Handle<Image>,
+);
+
+/// Everything the heatmap was last drawn from.
+#[derive(Clone, Debug, PartialEq)]
+struct FieldInputs {
+    grid: FieldGrid,
+    bodies: Vec<(Vec2, f32)>,
+    settings: FieldSettings,
+    gravity: GravityConfig,
+    pixels_per_length: f32,
+}
+
+/// Resamples the field over the viewport and redraws the heatmap, unless nothing it shows has
+/// changed since the last frame
+#[allow(clippy::too_many_arguments)]
+fn update_field_layer(
+    mut drawn: Local<Option<FieldInputs>>,
+    settings: Res<FieldSettings>,
+    gravity: Res<GravityConfig>,
+    units: Res<SimulationUnits>,
+    windows: Query<&Window>,
+    camera: Query<(&OrthographicProjection, &Transform), With<MainCamera>>,
+    bodies: Query<&CelestialBody>,
+    mut images: ResMut<Assets<Image>>,
+    mut sprite: Query<FieldSpriteParts, (With<FieldSprite>, Without<MainCamera>)>,
+) {
+    let Ok((mut sprite, mut transform, mut visibility, handle)) = sprite.get_single_mut() else {
+        return;
+    };
+    if settings.layer == FieldLayer::Off {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+    *visibility = Visibility::Inherited;
+
+    // Cover the viewport, turned with the camera
+    let (projection, camera_transform) = camera.single();
+    let size = windows.single().size() * projection.scale;
+    let columns = settings.resolution;
+    let rows = ((columns as f32 * size.y / size.x).round() as u32).max(1);
+    let grid = FieldGrid {
+        centre: camera_transform.translation.truncate(),
+        size,
+        axis: (camera_transform.rotation * Vec3::X).truncate().normalize(),
+        columns,
+        rows,
+    };
+
+    let inputs = FieldInputs {
+        grid,
+        bodies: bodies
+            .iter()
+            .map(|body| (body.position, body.mass))
+            .collect(),
+        settings: *settings,
+        gravity: *gravity,
+        pixels_per_length: units.pixels_per_length,
+    };
+    if drawn.as_ref() == Some(&inputs) {
+        return;
+    }
+
+    let values = sample_field(
+        settings.layer,
+        &inputs.bodies,
+        &gravity,
+        units.pixels_per_length,
+        grid.points(),
+    );
+    let Some(image) = images.get_mut(handle) else {
+        return;
+    };
+    let extent = Extent3d {
+        width: columns,
+        height: rows,
+        depth_or_array_layers: 1,
+    };
+    if image.texture_descriptor.size != extent {
+        image.resize(extent);
+    }
+    image.data = colour_field(settings.layer, &values, settings.colour_scale);
+
+    sprite.custom_size = Some(size);
+    transform.translation = grid.centre.extend(FIELD_DEPTH);
+    transform.rotation = camera_transform.rotation;
+    *drawn = Some(inputs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gravity::ForceLaw;
+
+    const PIXELS_PER_LENGTH: f32 = 100.0;
+
+    fn grid() -> FieldGrid {
+        FieldGrid {
+            centre: Vec2::new(50.0, 0.0),
+            size: Vec2::new(400.0, 200.0),
+            axis: Vec2::X,
+            columns: 4,
+            rows: 2,
+        }
+    }
+
+    #[test]
+    fn grid_covers_the_rectangle_from_the_top_left() {
+        let points: Vec<Vec2> = grid().points().collect();
+        assert_eq!(points.len(), 8);
+        assert_eq!(points[0], Vec2::new(-100.0, 50.0));
+        assert_eq!(points[7], Vec2::new(200.0, -50.0));
+
+        // A quarter turn puts the first row down the left hand side
+        let turned = FieldGrid {
+            axis: Vec2::Y,
+            ..grid()
+        };
+        let first = turned.points().next().unwrap();
+        assert!(first.distance(Vec2::new(0.0, -150.0)) < 1.0e-4, "{first}");
+    }
+
+    #[test]
+    fn field_matches_the_solver() {
+        let bodies = [(Vec2::new(0.0, 0.0), 10.0), (Vec2::new(300.0, 100.0), 2.0)];
+        let point = Vec2::new(120.0, -40.0);
+        for force_law in [ForceLaw::Newtonian, ForceLaw::Softened] {
+            let gravity = GravityConfig {
+                force_law,
+                ..default()
+            };
+
+            // The field strength is the acceleration a light test body would feel
+            let test_mass = 1.0e-6;
+            let positions = [point, bodies[0].0, bodies[1].0];
+            let masses = [test_mass, bodies[0].1, bodies[1].1];
+            let (_, accelerations) = crate::gravity::GravitySolver::Pairwise.accelerations(
+                &positions,
+                &masses,
+                &gravity,
+                PIXELS_PER_LENGTH,
+            );
+            let strength = sample_field(
+                FieldLayer::Strength,
+                &bodies,
+                &gravity,
+                PIXELS_PER_LENGTH,
+                [point].into_iter(),
+            )[0];
+            let expected = accelerations[0].length();
+            assert!((strength - expected).abs() < 1.0e-4 * expected);
+
+            // And the potential falls off the way the field points
+            let potential = |point: Vec2| {
+                sample_field(
+                    FieldLayer::Potential,
+                    &bodies,
+                    &gravity,
+                    PIXELS_PER_LENGTH,
+                    [point].into_iter(),
+                )[0]
+            };
+            let step = 0.01;
+            let gradient = Vec2::new(
+                potential(point + Vec2::X * step) - potential(point - Vec2::X * step),
+                potential(point + Vec2::Y * step) - potential(point - Vec2::Y * step),
+            ) / (2.0 * step);
+            let direction = accelerations[0].normalize();
+            assert!(gradient.normalize().dot(direction) < -0.99);
+        }
+    }
+
+    #[test]
+    fn deeper_wells_are_brighter() {
+        let values = [-10.0, -1.0, -0.5, f32::NEG_INFINITY];
+        let pixels = colour_field(FieldLayer::Potential, &values, 1.0);
+        assert_eq!(pixels.len(), 4 * values.len());
+
+        let brightness = |index: usize| {
+            pixels[4 * index..4 * index + 3]
+                .iter()
+                .map(|&channel| channel as u32)
+                .sum::<u32>()
+        };
+        // The highest sample is the zero level, and a body's centre saturates
+        assert_eq!(brightness(2), 5);
+        assert!(brightness(1) < brightness(0));
+        assert_eq!(&pixels[12..16], &[252, 255, 163, 255]);
+
+        // A larger colour scale darkens everything in between
+        let faded = colour_field(FieldLayer::Potential, &values, 4.0);
+        assert!(faded[0] as u32 + faded[1] as u32 + faded[2] as u32 <= brightness(0));
+    }
+
+    /// Blanks the heatmap, and tells whether it has been drawn again since it was last blanked.
+    fn redrawn(app: &mut App) -> bool {
+        let mut images = app.world_mut().resource_mut::<Assets<Image>>();
+        let (_, image) = images.iter_mut().next().unwrap();
+        let drawn = image.data.iter().any(|&byte| byte != 0);
+        image.data.fill(0);
+        drawn
+    }
+
+    #[test]
+    fn heatmap_is_only_redrawn_when_something_changes() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_asset::<Image>()
+            .insert_resource(FieldSettings {
+                layer: FieldLayer::Potential,
+                resolution: MIN_RESOLUTION,
+                ..default()
+            })
+            .init_resource::<GravityConfig>()
+            .init_resource::<SimulationUnits>()
+            .add_systems(Startup, setup_field_layer)
+            .add_systems(Update, update_field_layer);
+        app.world_mut().spawn(Window::default());
+        app.world_mut().spawn((
+            OrthographicProjection::default(),
+            Transform::default(),
+            MainCamera,
+        ));
+        let body = app
+            .world_mut()
+            .spawn(CelestialBody::default().with_mass(10.0))
+            .id();
+
+        app.update();
+        assert!(redrawn(&mut app));
+        app.update();
+        assert!(!redrawn(&mut app));
+
+        // Moving a body, the camera or the settings draws it again
+        app.world_mut()
+            .get_mut::<CelestialBody>(body)
+            .unwrap()
+            .position = Vec2::new(10.0, 0.0);
+        app.update();
+        assert!(redrawn(&mut app));
+
+        let mut camera = app
+            .world_mut()
+            .query_filtered::<&mut Transform, With<MainCamera>>();
+        camera.single_mut(app.world_mut()).translation.x += 10.0;
+        app.update();
+        assert!(redrawn(&mut app));
+
+        app.world_mut().resource_mut::<FieldSettings>().colour_scale = 2.0;
+        app.update();
+        assert!(redrawn(&mut app));
+        app.update();
+        assert!(!redrawn(&mut app));
+    }
+}
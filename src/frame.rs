@@ -0,0 +1,285 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+use crate::celestial_body::{CelestialBody, TwoBodyProblem};
+use crate::selection::Selected;
+use crate::trails::TrailHistory;
+
+pub struct FramePlugin;
+impl Plugin for FramePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReferenceFrame>()
+            .add_systems(Update, (toggle_co_rotating_frame, drop_lost_frame).chain());
+    }
+}
+
+/// The frame the system is drawn in.
+///
+/// Only the view changes: the bodies are always integrated, and their trails recorded, in
+/// inertial coordinates.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReferenceFrame {
+    #[default]
+    Inertial,
+    /// Turns with two bodies about their barycentre, with the secondary along the positive x axis.
+    CoRotating { primary: Entity, secondary: Entity },
+}
+
+/// Where a frame's origin and x axis sit in inertial coordinates, at one instant.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FramePose {
+    pub origin: Vec2,
+    /// Unit vector along the frame's x axis.
+    pub axis: Vec2,
+}
+
+impl FramePose {
+    pub const INERTIAL: Self = Self {
+        origin: Vec2::ZERO,
+        axis: Vec2::X,
+    };
+
+    /// The pose of the frame turning with two bodies, each given as a position and mass.
+    pub fn co_rotating(primary: (Vec2, f32), secondary: (Vec2, f32)) -> Self {
+        let (primary_position, primary_mass) = primary;
+        let (secondary_position, secondary_mass) = secondary;
+        Self {
+            origin: (primary_position * primary_mass + secondary_position * secondary_mass)
+                / (primary_mass + secondary_mass),
+            axis: (secondary_position - primary_position)
+                .try_normalize()
+                .unwrap_or(Vec2::X),
+        }
+    }
+
+    /// Converts an inertial position to frame coordinates.
+    pub fn to_frame(self, position: Vec2) -> Vec2 {
+        Vec2::new(self.axis.x, -self.axis.y).rotate(position - self.origin)
+    }
+
+    /// The inverse of [`Self::to_frame`].
+    pub fn to_world(self, position: Vec2) -> Vec2 {
+        self.origin + self.axis.rotate(position)
+    }
+
+    /// The rotation that lines a camera up with the frame.
+    pub fn rotation(&self) -> Quat {
+        Quat::from_rotation_z(self.axis.y.atan2(self.axis.x))
+    }
+}
+
+impl ReferenceFrame {
+    /// The pose given where each body is and how heavy it is, or `None` if the pair is missing.
+    fn pose(&self, body: impl Fn(Entity) -> Option<(Vec2, f32)>) -> Option<FramePose> {
+        match *self {
+            Self::Inertial => Some(FramePose::INERTIAL),
+            Self::CoRotating { primary, secondary } => {
+                Some(FramePose::co_rotating(body(primary)?, body(secondary)?))
+            }
+        }
+    }
+}
+
+/// Maps trail vertices, recorded in inertial coordinates, to where they are drawn now.
+///
+/// A vertex is put in the frame as it stood when the vertex was sampled, then placed back in the
+/// world with the frame as it stands now, so the camera turning with the frame sees the path the
+/// body took through the frame.
+pub enum TrailProjection {
+    Inertial,
+    CoRotating {
+        now: FramePose,
+        /// Sample id of the first pose.
+        first_sample: u64,
+        /// The pose at each sample in the history, if the pair was around then.
+        poses: Vec<Option<FramePose>>,
+    },
+}
+
+impl TrailProjection {
+    /// Works out the frame at every sample in `history`.
+    ///
+    /// `body` gives the live position and mass of a body. Masses are taken as they are now.
+    pub fn new(
+        frame: ReferenceFrame,
+        history: &TrailHistory,
+        body: impl Fn(Entity) -> Option<(Vec2, f32)>,
+    ) -> Self {
+        let Some(now) = frame.pose(&body) else {
+            return Self::Inertial;
+        };
+        if frame == ReferenceFrame::Inertial {
+            return Self::Inertial;
+        }
+
+        let poses = history
+            .samples()
+            .map(|(sample, _)| {
+                frame.pose(|entity| {
+                    let (_, mass) = body(entity)?;
+                    Some((history.position(sample, entity)?, mass))
+                })
+            })
+            .collect();
+        Self::CoRotating {
+            now,
+            first_sample: history.first_sample(),
+            poses,
+        }
+    }
+
+    /// Where a vertex recorded at `sample` is drawn, or `None` if the frame wasn't known then.
+    pub fn project(&self, position: Vec2, sample: u64) -> Option<Vec2> {
+        match self {
+            Self::Inertial => Some(position),
+            Self::CoRotating {
+                now,
+                first_sample,
+                poses,
+            } => {
+                let index = usize::try_from(sample.checked_sub(*first_sample)?).ok()?;
+                let then = (*poses.get(index)?)?;
+                Some(now.to_world(then.to_frame(position)))
+            }
+        }
+    }
+}
+
+/// The reference frame as it stands, for the systems that draw in it.
+#[derive(SystemParam)]
+pub struct FrameView<'w, 's> {
+    frame: Res<'w, ReferenceFrame>,
+    history: Res<'w, TrailHistory>,
+    bodies: Query<'w, 's, &'static CelestialBody>,
+}
+
+impl FrameView<'_, '_> {
+    fn body(&self, entity: Entity) -> Option<(Vec2, f32)> {
+        let body = self.bodies.get(entity).ok()?;
+        Some((body.position, body.mass))
+    }
+
+    /// Whether a different frame was picked since the system last ran.
+    pub fn is_changed(&self) -> bool {
+        self.frame.is_changed()
+    }
+
+    /// The pose of the frame now, falling back to inertial if the pair is gone.
+    pub fn now(&self) -> FramePose {
+        self.frame
+            .pose(|entity| self.body(entity))
+            .unwrap_or(FramePose::INERTIAL)
+    }
+
+    pub fn trail_projection(&self) -> TrailProjection {
+        TrailProjection::new(*self.frame, &self.history, |entity| self.body(entity))
+    }
+}
+
+/// Switches to the frame turning with the selected body and its primary when O is pressed, and
+/// back to the inertial frame when pressed again
+fn toggle_co_rotating_frame(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut frame: ResMut<ReferenceFrame>,
+    selected: Query<(Entity, &CelestialBody, &TwoBodyProblem), With<Selected>>,
+    bodies: Query<&CelestialBody>,
+) {
+    if !keys.just_pressed(KeyCode::KeyO) {
+        return;
+    }
+
+    *frame = match (*frame, selected.get_single()) {
+        (ReferenceFrame::Inertial, Ok((entity, body, two_body_problem))) => {
+            let Some((other, other_body)) = two_body_problem
+                .entity
+                .and_then(|other| Some((other, bodies.get(other).ok()?)))
+            else {
+                return;
+            };
+            // The heavier body is the primary
+            if body.mass > other_body.mass {
+                ReferenceFrame::CoRotating {
+                    primary: entity,
+                    secondary: other,
+                }
+            } else {
+                ReferenceFrame::CoRotating {
+                    primary: other,
+                    secondary: entity,
+                }
+            }
+        }
+        (ReferenceFrame::Inertial, Err(_)) => return,
+        (ReferenceFrame::CoRotating { .. }, _) => ReferenceFrame::Inertial,
+    };
+    info!("Reference frame: {:?}", *frame);
+}
+
+/// Goes back to the inertial frame once either body of the pair is merged or deleted
+fn drop_lost_frame(mut frame: ResMut<ReferenceFrame>, bodies: Query<(), With<CelestialBody>>) {
+    if let ReferenceFrame::CoRotating { primary, secondary } = *frame {
+        if !bodies.contains(primary) || !bodies.contains(secondary) {
+            *frame = ReferenceFrame::Inertial;
+            info!("Reference frame: {:?}", *frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_coordinates_round_trip() {
+        let pose = FramePose::co_rotating((Vec2::new(1.0, 1.0), 3.0), (Vec2::new(1.0, 5.0), 1.0));
+        // The barycentre is the origin, and the secondary lies along the x axis
+        assert_eq!(pose.origin, Vec2::new(1.0, 2.0));
+        assert!(
+            pose.to_frame(Vec2::new(1.0, 5.0))
+                .distance(Vec2::new(3.0, 0.0))
+                < 1.0e-6
+        );
+
+        let position = Vec2::new(-4.0, 7.5);
+        assert!(pose.to_world(pose.to_frame(position)).distance(position) < 1.0e-5);
+    }
+
+    #[test]
+    fn trails_are_reprojected_with_the_frame_at_each_sample() {
+        let [primary, secondary, other] = [0, 1, 2].map(Entity::from_raw);
+        let frame = ReferenceFrame::CoRotating { primary, secondary };
+
+        // The pair turns a quarter of a turn between the two samples
+        let mut history = TrailHistory::default();
+        let first = history.record(vec![
+            (primary, Vec2::new(-1.0, 0.0)),
+            (secondary, Vec2::new(1.0, 0.0)),
+            (other, Vec2::new(2.0, 0.0)),
+        ]);
+        let second = history.record(vec![
+            (primary, Vec2::new(0.0, -1.0)),
+            (secondary, Vec2::new(0.0, 1.0)),
+            (other, Vec2::new(2.0, 0.0)),
+        ]);
+        let now = |entity: Entity| {
+            history
+                .position(second, entity)
+                .map(|position| (position, 1.0))
+        };
+
+        let projection = TrailProjection::new(frame, &history, now);
+        // A body that sat still in inertial space swings backwards through the frame
+        let then = projection.project(Vec2::new(2.0, 0.0), first).unwrap();
+        assert!(then.distance(Vec2::new(0.0, 2.0)) < 1.0e-6, "{then}");
+        let latest = projection.project(Vec2::new(2.0, 0.0), second).unwrap();
+        assert!(latest.distance(Vec2::new(2.0, 0.0)) < 1.0e-6, "{latest}");
+        // Before the history starts there is no frame to project with
+        assert_eq!(projection.project(Vec2::ZERO, first - 1), None);
+
+        let inertial = TrailProjection::new(ReferenceFrame::Inertial, &history, now);
+        assert_eq!(
+            inertial.project(Vec2::new(2.0, 0.0), first),
+            Some(Vec2::new(2.0, 0.0))
+        );
+    }
+}
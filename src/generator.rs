@@ -0,0 +1,486 @@
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+
+use crate::scenario::{BodyDescription, TrailSettings};
+
+/// Randomly placed bodies, laid out by one of the presets.
+///
+/// Lengths and masses are in the scenario's units. The same seed always gives the same bodies.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Generator {
+    pub preset: Preset,
+    pub seed: u64,
+    /// Number of bodies besides the stars or cores.
+    pub count: usize,
+    pub mass: MassDistribution,
+    pub radial: RadialProfile,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Preset {
+    /// A protoplanetary disk on circular orbits around a central star.
+    Disk { star_mass: f32 },
+    /// A star cluster with Plummer's velocity distribution, flattened into the plane and scaled
+    /// to virial equilibrium.
+    Plummer,
+    /// Two stars on a circular orbit, with planets circling both.
+    Binary {
+        star_masses: [f32; 2],
+        separation: f32,
+    },
+    /// Two disk galaxies around heavy cores, on a collision course.
+    Galaxies {
+        core_mass: f32,
+        separation: f32,
+        speed: f32,
+    },
+}
+
+/// Body masses follow a power law, `dN/dm ∝ m^-exponent`, between `min` and `max`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct MassDistribution {
+    pub min: f32,
+    pub max: f32,
+    pub exponent: f32,
+}
+
+/// How far bodies are placed from the centre of their system.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum RadialProfile {
+    /// Surface density falls off as `r^-exponent` between `inner` and `outer`.
+    PowerLaw {
+        inner: f32,
+        outer: f32,
+        exponent: f32,
+    },
+    /// Plummer's density profile with a core of radius `scale`, cut off at `outer`.
+    Plummer { scale: f32, outer: f32 },
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self::disk()
+    }
+}
+
+impl Generator {
+    pub fn disk() -> Self {
+        Self {
+            preset: Preset::Disk { star_mass: 10.0 },
+            seed: 0,
+            count: 100,
+            mass: MassDistribution {
+                min: 0.02,
+                max: 0.2,
+                exponent: 1.5,
+            },
+            radial: RadialProfile::PowerLaw {
+                inner: 1.0,
+                outer: 4.0,
+                exponent: 1.0,
+            },
+        }
+    }
+
+    pub fn plummer() -> Self {
+        Self {
+            preset: Preset::Plummer,
+            seed: 0,
+            count: 100,
+            mass: MassDistribution {
+                min: 0.1,
+                max: 1.0,
+                exponent: 2.35,
+            },
+            radial: RadialProfile::Plummer {
+                scale: 1.0,
+                outer: 4.0,
+            },
+        }
+    }
+
+    pub fn binary() -> Self {
+        Self {
+            preset: Preset::Binary {
+                star_masses: [6.0, 4.0],
+                separation: 0.8,
+            },
+            seed: 0,
+            count: 20,
+            mass: MassDistribution {
+                min: 0.02,
+                max: 0.1,
+                exponent: 1.5,
+            },
+            // Circumbinary orbits are only stable a few separations out
+            radial: RadialProfile::PowerLaw {
+                inner: 2.5,
+                outer: 5.0,
+                exponent: 1.0,
+            },
+        }
+    }
+
+    pub fn galaxies() -> Self {
+        Self {
+            preset: Preset::Galaxies {
+                core_mass: 10.0,
+                separation: 8.0,
+                speed: 0.25,
+            },
+            seed: 0,
+            count: 200,
+            mass: MassDistribution {
+                min: 0.01,
+                max: 0.05,
+                exponent: 0.0,
+            },
+            radial: RadialProfile::PowerLaw {
+                inner: 0.5,
+                outer: 2.5,
+                exponent: 0.0,
+            },
+        }
+    }
+
+    pub fn with_seed(&self, seed: u64) -> Self {
+        Self {
+            seed,
+            ..self.clone()
+        }
+    }
+
+    pub fn with_count(&self, count: usize) -> Self {
+        Self {
+            count,
+            ..self.clone()
+        }
+    }
+
+    /// How far from the origin bodies start out, for framing the camera.
+    pub fn extent(&self) -> f32 {
+        let outer = match self.radial {
+            RadialProfile::PowerLaw { outer, .. } | RadialProfile::Plummer { outer, .. } => outer,
+        };
+        match self.preset {
+            Preset::Galaxies { separation, .. } => separation / 2.0 + outer,
+            _ => outer,
+        }
+    }
+
+    /// Lays out the bodies, on orbits for the given gravitational constant.
+    ///
+    /// The barycentre of the generated bodies is at rest at the origin.
+    pub fn generate(&self, gravitational_constant: f32) -> Vec<BodyDescription> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let g = gravitational_constant;
+
+        let mut bodies = match self.preset {
+            Preset::Disk { star_mass } => {
+                let mut bodies = vec![star("Star", star_mass, Vec2::ZERO, Vec2::ZERO)];
+                for _ in 0..self.count {
+                    let position = random_direction(&mut rng) * self.radius(&mut rng);
+                    bodies.push(self.orbiting(&mut rng, position, g * star_mass, true));
+                }
+                bodies
+            }
+            Preset::Plummer => {
+                let mut bodies: Vec<BodyDescription> = (0..self.count)
+                    .map(|_| {
+                        let (position, velocity) = self.plummer_state(&mut rng);
+                        BodyDescription {
+                            position,
+                            velocity,
+                            mass: self.mass.sample(&mut rng),
+                            trail: no_trail(),
+                            ..default()
+                        }
+                    })
+                    .collect();
+                recentre(&mut bodies);
+                virialize(&mut bodies, g);
+                bodies
+            }
+            Preset::Binary {
+                star_masses: [m1, m2],
+                separation,
+            } => {
+                let total = m1 + m2;
+                let speed = (g * total / separation).sqrt();
+                let mut bodies = vec![
+                    star(
+                        "Star A",
+                        m1,
+                        Vec2::new(-separation * m2 / total, 0.0),
+                        Vec2::new(0.0, -speed * m2 / total),
+                    ),
+                    star(
+                        "Star B",
+                        m2,
+                        Vec2::new(separation * m1 / total, 0.0),
+                        Vec2::new(0.0, speed * m1 / total),
+                    ),
+                ];
+                for _ in 0..self.count {
+                    let position = random_direction(&mut rng) * self.radius(&mut rng);
+                    bodies.push(self.orbiting(&mut rng, position, g * total, true));
+                }
+                bodies
+            }
+            Preset::Galaxies {
+                core_mass,
+                separation,
+                speed,
+            } => {
+                // Offset sideways so they swing past each other rather than meet head on
+                let centre = Vec2::new(separation / 2.0, separation / 8.0);
+                let mut bodies = Vec::new();
+                // The first galaxy takes the odd body out
+                let counts = [self.count - self.count / 2, self.count / 2];
+                for ((name, centre, velocity, prograde), count) in [
+                    ("Core A", -centre, Vec2::new(speed, 0.0), true),
+                    ("Core B", centre, Vec2::new(-speed, 0.0), false),
+                ]
+                .into_iter()
+                .zip(counts)
+                {
+                    bodies.push(star(name, core_mass, centre, velocity));
+                    for _ in 0..count {
+                        let offset = random_direction(&mut rng) * self.radius(&mut rng);
+                        let mut body = self.orbiting(&mut rng, offset, g * core_mass, prograde);
+                        body.position += centre;
+                        body.velocity += velocity;
+                        body.trail = no_trail();
+                        bodies.push(body);
+                    }
+                }
+                bodies
+            }
+        };
+
+        recentre(&mut bodies);
+        bodies
+    }
+
+    /// A distance from the centre following the radial profile.
+    fn radius(&self, rng: &mut StdRng) -> f32 {
+        match self.radial {
+            // The number of bodies in a ring grows as r^(1 - exponent)
+            RadialProfile::PowerLaw {
+                inner,
+                outer,
+                exponent,
+            } => power_law(rng, inner, outer, 1.0 - exponent),
+            RadialProfile::Plummer { scale, outer } => loop {
+                // Inverts the enclosed mass fraction r³ / (r² + a²)^(3/2)
+                let fraction: f32 = rng.gen_range(f32::EPSILON..1.0);
+                let radius = scale / (fraction.powf(-2.0 / 3.0) - 1.0).sqrt();
+                if radius <= outer {
+                    break radius;
+                }
+            },
+        }
+    }
+
+    /// A body on a circular orbit at `position` around a mass at the origin.
+    fn orbiting(
+        &self,
+        rng: &mut StdRng,
+        position: Vec2,
+        mu: f32,
+        prograde: bool,
+    ) -> BodyDescription {
+        let speed = (mu / position.length()).sqrt();
+        let direction = if prograde { 1.0 } else { -1.0 };
+        BodyDescription {
+            position,
+            velocity: direction * speed * position.normalize().perp(),
+            mass: self.mass.sample(rng),
+            ..default()
+        }
+    }
+
+    /// A position and velocity drawn from Plummer's distribution function, in units where the
+    /// cluster's G M / a is one.
+    fn plummer_state(&self, rng: &mut StdRng) -> (Vec2, Vec2) {
+        let radius = self.radius(rng);
+        let scale = match self.radial {
+            RadialProfile::Plummer { scale, .. } => scale,
+            RadialProfile::PowerLaw { outer, .. } => outer,
+        };
+
+        // Von Neumann rejection for the fraction of the escape speed, from Aarseth et al. (1974)
+        let fraction = loop {
+            let q: f32 = rng.gen_range(0.0..1.0);
+            let g: f32 = rng.gen_range(0.0..0.1);
+            if g < q * q * (1.0 - q * q).powf(3.5) {
+                break q;
+            }
+        };
+        let escape_speed = 2.0_f32.sqrt() * (1.0 + (radius / scale).powi(2)).powf(-0.25);
+
+        (
+            random_direction(rng) * radius,
+            random_direction(rng) * fraction * escape_speed,
+        )
+    }
+}
+
+impl MassDistribution {
+    pub fn sample(&self, rng: &mut StdRng) -> f32 {
+        power_law(rng, self.min, self.max, -self.exponent)
+    }
+}
+
+/// A unit vector pointing in a uniformly random direction.
+pub fn random_direction(rng: &mut StdRng) -> Vec2 {
+    Vec2::from_angle(rng.gen_range(0.0..TAU))
+}
+
+/// Samples `x` between `min` and `max` with probability density proportional to `x^exponent`.
+fn power_law(rng: &mut StdRng, min: f32, max: f32, exponent: f32) -> f32 {
+    let uniform: f32 = rng.gen_range(0.0..=1.0);
+    let k = exponent + 1.0;
+    if k.abs() < 1.0e-6 {
+        min * (max / min).powf(uniform)
+    } else {
+        (min.powf(k) + uniform * (max.powf(k) - min.powf(k))).powf(1.0 / k)
+    }
+}
+
+fn star(name: &str, mass: f32, position: Vec2, velocity: Vec2) -> BodyDescription {
+    BodyDescription {
+        name: name.to_string(),
+        position,
+        velocity,
+        mass,
+        ..default()
+    }
+}
+
+fn no_trail() -> TrailSettings {
+    TrailSettings {
+        enabled: false,
+        color: None,
+    }
+}
+
+/// Scales the velocities so twice the kinetic energy balances the potential energy.
+fn virialize(bodies: &mut [BodyDescription], gravitational_constant: f32) {
+    let kinetic: f32 = bodies
+        .iter()
+        .map(|body| 0.5 * body.mass * body.velocity.length_squared())
+        .sum();
+    let mut potential = 0.0;
+    for (i, body) in bodies.iter().enumerate() {
+        for other in &bodies[i + 1..] {
+            potential -= gravitational_constant * body.mass * other.mass
+                / body.position.distance(other.position);
+        }
+    }
+
+    if kinetic > 0.0 {
+        let scale = (-potential / (2.0 * kinetic)).sqrt();
+        for body in bodies {
+            body.velocity *= scale;
+        }
+    }
+}
+
+/// Moves the barycentre to the origin and brings it to rest.
+fn recentre(bodies: &mut [BodyDescription]) {
+    let mass: f32 = bodies.iter().map(|body| body.mass).sum();
+    let centre = bodies
+        .iter()
+        .map(|body| body.mass * body.position)
+        .sum::<Vec2>()
+        / mass;
+    let velocity = bodies
+        .iter()
+        .map(|body| body.mass * body.velocity)
+        .sum::<Vec2>()
+        / mass;
+    for body in bodies {
+        body.position -= centre;
+        body.velocity -= velocity;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const G: f32 = 0.1;
+
+    fn presets() -> [Generator; 4] {
+        [
+            Generator::disk(),
+            Generator::plummer(),
+            Generator::binary(),
+            Generator::galaxies(),
+        ]
+    }
+
+    #[test]
+    fn seeds_are_repeatable() {
+        for generator in presets() {
+            let first = generator.with_seed(3).generate(G);
+            assert_eq!(first, generator.with_seed(3).generate(G));
+            assert_ne!(first, generator.with_seed(4).generate(G));
+        }
+    }
+
+    #[test]
+    fn barycentre_is_at_rest_at_the_origin() {
+        for generator in presets() {
+            let bodies = generator.with_count(50).generate(G);
+            let centre: Vec2 = bodies.iter().map(|body| body.mass * body.position).sum();
+            let momentum: Vec2 = bodies.iter().map(|body| body.mass * body.velocity).sum();
+            assert!(centre.length() < 1.0e-3, "{:?}: {centre}", generator.preset);
+            assert!(
+                momentum.length() < 1.0e-3,
+                "{:?}: {momentum}",
+                generator.preset
+            );
+        }
+    }
+
+    #[test]
+    fn disk_follows_its_profiles() {
+        let generator = Generator::disk().with_count(200);
+        let bodies = generator.generate(G);
+        assert_eq!(bodies.len(), 201);
+
+        let (star, disk) = bodies.split_first().unwrap();
+        for body in disk {
+            let offset = body.position - star.position;
+            let velocity = body.velocity - star.velocity;
+            assert!((0.02..=0.2).contains(&body.mass), "{}", body.mass);
+            assert!((0.99..=4.01).contains(&offset.length()), "{offset}");
+            // Circular and anticlockwise
+            let speed = (G * 10.0 / offset.length()).sqrt();
+            assert!((velocity.length() - speed).abs() < 1.0e-3 * speed);
+            assert!(offset.perp_dot(velocity) > 0.0);
+        }
+    }
+
+    #[test]
+    fn galaxies_split_an_odd_count() {
+        let bodies = Generator::galaxies().with_count(51).generate(G);
+        assert_eq!(bodies.len(), 2 + 51);
+    }
+
+    #[test]
+    fn plummer_cluster_is_virialized() {
+        let bodies = Generator::plummer().with_count(200).generate(G);
+        let mut copy = bodies.clone();
+        virialize(&mut copy, G);
+        for (body, copy) in bodies.iter().zip(&copy) {
+            assert!(body.velocity.distance(copy.velocity) < 1.0e-3 * body.velocity.length());
+        }
+    }
+}
@@ -0,0 +1,554 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Selects the algorithm used to accumulate gravitational forces between bodies.
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub enum GravitySolver {
+    /// Exact O(n²) summation over every pair of bodies.
+    Pairwise,
+    /// Approximate O(n log n) summation using a Barnes-Hut quadtree.
+    ///
+    /// `theta` is the opening angle: a node of width `s` at distance `d` is treated as a single
+    /// mass when `s / d < theta`. Zero opens every node and reproduces the pairwise result.
+    BarnesHut { theta: f32 },
+}
+
+impl Default for GravitySolver {
+    fn default() -> Self {
+        Self::BarnesHut { theta: 0.5 }
+    }
+}
+
+/// How the attraction between two bodies depends on their separation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ForceLaw {
+    /// `G m1 m2 / r²`
+    #[default]
+    Newtonian,
+    /// Plummer softening, `G m1 m2 r / (r² + ε²)^(3/2)`, which stays finite when bodies overlap.
+    Softened,
+    /// Newtonian plus a Yukawa term, from the potential `-G m1 m2 (1 + strength e^(-r/range)) / r`.
+    Yukawa { strength: f32, range: f32 },
+    /// `G m1 m2 / r^exponent`
+    PowerLaw { exponent: f32 },
+    /// No gravity at all.
+    None,
+}
+
+impl ForceLaw {
+    /// The law after this one, for cycling through them from the keyboard.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Newtonian => Self::Softened,
+            Self::Softened => Self::Yukawa {
+                strength: 1.0,
+                range: 1.0,
+            },
+            Self::Yukawa { .. } => Self::PowerLaw { exponent: 1.0 },
+            Self::PowerLaw { .. } => Self::None,
+            Self::None => Self::Newtonian,
+        }
+    }
+}
+
+/// Parameters of the gravitational force law.
+///
+/// Lengths are in simulation length units, like the positions the solvers work with.
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct GravityConfig {
+    pub gravitational_constant: f32,
+    /// The Plummer softening length ε used by [`ForceLaw::Softened`].
+    pub softening_length: f32,
+    pub force_law: ForceLaw,
+}
+
+impl Default for GravityConfig {
+    fn default() -> Self {
+        Self {
+            gravitational_constant: 0.1,
+            softening_length: 0.1,
+            force_law: ForceLaw::Newtonian,
+        }
+    }
+}
+
+impl GravityConfig {
+    /// The gravitational parameter `G (m1 + m2)` of a pair of bodies, in pixels³ per time unit².
+    ///
+    /// Accelerations are G m / r² with r in length units, scaled back up to pixels, so mu picks up
+    /// three factors of the scale. `None` unless the force law is Newtonian, since only the inverse
+    /// square law has Keplerian orbits.
+    pub fn gravitational_parameter(&self, total_mass: f32, pixels_per_length: f32) -> Option<f32> {
+        (self.force_law == ForceLaw::Newtonian)
+            .then(|| self.gravitational_constant * total_mass * pixels_per_length.powi(3))
+    }
+
+    /// The force on `m1` due to `m2` located at `offset` from it.
+    pub fn force(&self, m1: f32, m2: f32, offset: Vec2) -> Vec2 {
+        let g = self.gravitational_constant;
+        let distance = offset.length();
+        let magnitude = match self.force_law {
+            ForceLaw::Newtonian => g * m1 * m2 / (distance * distance),
+            ForceLaw::Softened => {
+                let softened = distance * distance + self.softening_length.powi(2);
+                g * m1 * m2 * distance / (softened * softened.sqrt())
+            }
+            ForceLaw::Yukawa { strength, range } => {
+                let yukawa = strength * (1.0 + distance / range) * (-distance / range).exp();
+                g * m1 * m2 * (1.0 + yukawa) / (distance * distance)
+            }
+            ForceLaw::PowerLaw { exponent } => g * m1 * m2 / distance.powf(exponent),
+            ForceLaw::None => return Vec2::ZERO,
+        };
+        magnitude / distance * offset
+    }
+
+    /// The potential energy of two bodies `distance` apart, consistent with [`Self::force`].
+    pub fn potential(&self, m1: f32, m2: f32, distance: f32) -> f32 {
+        let g = self.gravitational_constant;
+        match self.force_law {
+            ForceLaw::Newtonian => -g * m1 * m2 / distance,
+            ForceLaw::Softened => {
+                -g * m1 * m2 / (distance * distance + self.softening_length.powi(2)).sqrt()
+            }
+            ForceLaw::Yukawa { strength, range } => {
+                -g * m1 * m2 * (1.0 + strength * (-distance / range).exp()) / distance
+            }
+            ForceLaw::PowerLaw { exponent: 1.0 } => g * m1 * m2 * distance.ln(),
+            ForceLaw::PowerLaw { exponent } => {
+                -g * m1 * m2 / ((exponent - 1.0) * distance.powf(exponent - 1.0))
+            }
+            ForceLaw::None => 0.0,
+        }
+    }
+}
+
+/// The minimal description of a body needed to compute gravity.
+#[derive(Clone, Copy, Debug)]
+pub struct PointMass {
+    pub position: Vec2,
+    pub mass: f32,
+}
+
+/// The accumulated gravitational pull on a single body.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Attraction {
+    pub force: Vec2,
+    /// Index and force magnitude of the most influential body.
+    pub dominant: Option<(usize, f32)>,
+}
+
+impl Attraction {
+    fn add(&mut self, other: usize, force: Vec2) {
+        self.add_with_influence(other, force, force.length());
+    }
+
+    fn add_with_influence(&mut self, other: usize, force: Vec2, influence: f32) {
+        self.force += force;
+        if self
+            .dominant
+            .is_none_or(|(_, strongest)| strongest < influence)
+        {
+            self.dominant = Some((other, influence));
+        }
+    }
+}
+
+impl GravitySolver {
+    /// Computes the attraction on every body, in the same order as `bodies`.
+    pub fn solve(&self, bodies: &[PointMass], gravity: &GravityConfig) -> Vec<Attraction> {
+        match *self {
+            Self::Pairwise => pairwise(bodies, gravity),
+            Self::BarnesHut { theta } => {
+                let tree = QuadTree::new(bodies);
+                (0..bodies.len())
+                    .map(|index| tree.attraction(bodies, index, gravity, theta))
+                    .collect()
+            }
+        }
+    }
+}
+
+impl GravitySolver {
+    /// Solves for bodies positioned in pixels, returning their attractions and accelerations.
+    ///
+    /// Positions are scaled to length units for the force law, and the accelerations are scaled
+    /// back up to pixels per time unit squared.
+    pub fn accelerations(
+        &self,
+        positions: &[Vec2],
+        masses: &[f32],
+        gravity: &GravityConfig,
+        pixels_per_length: f32,
+    ) -> (Vec<Attraction>, Vec<Vec2>) {
+        let bodies: Vec<PointMass> = positions
+            .iter()
+            .zip(masses)
+            .map(|(&position, &mass)| PointMass {
+                position: position / pixels_per_length,
+                mass,
+            })
+            .collect();
+        let attractions = self.solve(&bodies, gravity);
+        let accelerations = attractions
+            .iter()
+            .zip(masses)
+            .map(|(attraction, mass)| attraction.force / *mass * pixels_per_length)
+            .collect();
+        (attractions, accelerations)
+    }
+}
+
+fn pairwise(bodies: &[PointMass], gravity: &GravityConfig) -> Vec<Attraction> {
+    let mut attractions = vec![Attraction::default(); bodies.len()];
+    for i in 0..bodies.len() {
+        for j in (i + 1)..bodies.len() {
+            let force = gravity.force(
+                bodies[i].mass,
+                bodies[j].mass,
+                bodies[j].position - bodies[i].position,
+            );
+
+            if force.is_finite() {
+                attractions[i].add(j, force);
+                attractions[j].add(i, -force);
+            }
+        }
+    }
+    attractions
+}
+
+/// Stops subdividing so that coincident bodies cannot recurse forever.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug)]
+enum NodeKind {
+    Empty,
+    Leaf(Vec<usize>),
+    Branch([usize; 4]),
+}
+
+#[derive(Debug)]
+struct Node {
+    center: Vec2,
+    half_size: f32,
+    mass: f32,
+    center_of_mass: Vec2,
+    /// The heaviest body in this node, used as the most influential body when it is approximated.
+    heaviest: usize,
+    kind: NodeKind,
+}
+
+impl Node {
+    fn new(center: Vec2, half_size: f32) -> Self {
+        Self {
+            center,
+            half_size,
+            mass: 0.0,
+            center_of_mass: center,
+            heaviest: 0,
+            kind: NodeKind::Empty,
+        }
+    }
+
+    fn quadrant(&self, position: Vec2) -> usize {
+        (position.x >= self.center.x) as usize + 2 * (position.y >= self.center.y) as usize
+    }
+
+    fn contains(&self, position: Vec2) -> bool {
+        (position - self.center).abs().max_element() <= self.half_size
+    }
+}
+
+/// A Barnes-Hut quadtree, stored as an arena of nodes with the root at index 0.
+#[derive(Debug)]
+pub struct QuadTree {
+    nodes: Vec<Node>,
+}
+
+impl QuadTree {
+    pub fn new(bodies: &[PointMass]) -> Self {
+        let (min, max) = bodies
+            .iter()
+            .filter(|body| body.position.is_finite())
+            .fold((Vec2::MAX, Vec2::MIN), |(min, max), body| {
+                (min.min(body.position), max.max(body.position))
+            });
+
+        let (center, half_size) = if min.cmple(max).all() {
+            (
+                (min + max) / 2.0,
+                ((max - min).max_element() / 2.0).max(1.0e-3) * 1.01,
+            )
+        } else {
+            (Vec2::ZERO, 1.0)
+        };
+
+        let mut tree = Self {
+            nodes: vec![Node::new(center, half_size)],
+        };
+        for (index, body) in bodies.iter().enumerate() {
+            if body.position.is_finite() {
+                tree.insert(0, index, bodies, 0);
+            }
+        }
+        tree.summarize(0, bodies);
+        tree
+    }
+
+    fn insert(&mut self, node: usize, body: usize, bodies: &[PointMass], depth: usize) {
+        match &mut self.nodes[node].kind {
+            NodeKind::Empty => self.nodes[node].kind = NodeKind::Leaf(vec![body]),
+            NodeKind::Leaf(contents) if depth >= MAX_DEPTH => contents.push(body),
+            NodeKind::Leaf(_) => {
+                let children = self.subdivide(node);
+                let NodeKind::Leaf(contents) =
+                    std::mem::replace(&mut self.nodes[node].kind, NodeKind::Branch(children))
+                else {
+                    unreachable!()
+                };
+                for other in contents.into_iter().chain(std::iter::once(body)) {
+                    self.insert(node, other, bodies, depth);
+                }
+            }
+            NodeKind::Branch(children) => {
+                let children = *children;
+                let child = children[self.nodes[node].quadrant(bodies[body].position)];
+                self.insert(child, body, bodies, depth + 1);
+            }
+        }
+    }
+
+    /// Creates the four children of a node, ordered to match [`Node::quadrant`].
+    fn subdivide(&mut self, node: usize) -> [usize; 4] {
+        let half_size = self.nodes[node].half_size / 2.0;
+        let center = self.nodes[node].center;
+        let first = self.nodes.len();
+        for offset in [
+            Vec2::new(-1.0, -1.0),
+            Vec2::new(1.0, -1.0),
+            Vec2::new(-1.0, 1.0),
+            Vec2::new(1.0, 1.0),
+        ] {
+            self.nodes
+                .push(Node::new(center + offset * half_size, half_size));
+        }
+        [first, first + 1, first + 2, first + 3]
+    }
+
+    /// Fills in the mass, center of mass and heaviest body of a node and its children.
+    fn summarize(&mut self, node: usize, bodies: &[PointMass]) {
+        let contents: Vec<(f32, Vec2, usize, f32)> = match &self.nodes[node].kind {
+            NodeKind::Empty => return,
+            NodeKind::Leaf(contents) => contents
+                .iter()
+                .map(|&i| (bodies[i].mass, bodies[i].position, i, bodies[i].mass))
+                .collect(),
+            NodeKind::Branch(children) => {
+                let children = *children;
+                children
+                    .iter()
+                    .filter_map(|&child| {
+                        self.summarize(child, bodies);
+                        let child = &self.nodes[child];
+                        (child.mass > 0.0).then(|| {
+                            (
+                                child.mass,
+                                child.center_of_mass,
+                                child.heaviest,
+                                bodies[child.heaviest].mass,
+                            )
+                        })
+                    })
+                    .collect()
+            }
+        };
+
+        let mass: f32 = contents.iter().map(|(mass, ..)| mass).sum();
+        let node = &mut self.nodes[node];
+        if mass > 0.0 {
+            node.mass = mass;
+            node.center_of_mass = contents
+                .iter()
+                .map(|(mass, position, ..)| *mass * *position)
+                .sum::<Vec2>()
+                / mass;
+        }
+        if let Some((.., heaviest, _)) = contents.iter().max_by(|a, b| a.3.total_cmp(&b.3)) {
+            node.heaviest = *heaviest;
+        }
+    }
+
+    /// Walks the tree to accumulate the attraction on a single body.
+    pub fn attraction(
+        &self,
+        bodies: &[PointMass],
+        index: usize,
+        gravity: &GravityConfig,
+        theta: f32,
+    ) -> Attraction {
+        let mut attraction = Attraction::default();
+        let body = bodies[index];
+        if !body.position.is_finite() {
+            return attraction;
+        }
+
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            match &node.kind {
+                NodeKind::Empty => {}
+                NodeKind::Leaf(contents) => {
+                    for &other in contents.iter().filter(|&&other| other != index) {
+                        let force = gravity.force(
+                            body.mass,
+                            bodies[other].mass,
+                            bodies[other].position - body.position,
+                        );
+                        if force.is_finite() {
+                            attraction.add(other, force);
+                        }
+                    }
+                }
+                NodeKind::Branch(children) => {
+                    let offset = node.center_of_mass - body.position;
+                    let width = 2.0 * node.half_size;
+                    let far_enough = width < theta * offset.length();
+
+                    if far_enough && !node.contains(body.position) {
+                        let force = gravity.force(body.mass, node.mass, offset);
+                        let influence = gravity
+                            .force(body.mass, bodies[node.heaviest].mass, offset)
+                            .length();
+                        if force.is_finite() {
+                            attraction.add_with_influence(node.heaviest, force, influence);
+                        }
+                    } else {
+                        stack.extend(children);
+                    }
+                }
+            }
+        }
+        attraction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_cluster(count: usize) -> Vec<PointMass> {
+        let mut rng = StdRng::seed_from_u64(42);
+        (0..count)
+            .map(|_| PointMass {
+                position: Vec2::new(rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0)),
+                mass: rng.gen_range(0.1..10.0),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn barnes_hut_matches_pairwise() {
+        let bodies = random_cluster(500);
+        let gravity = GravityConfig::default();
+        let exact = GravitySolver::Pairwise.solve(&bodies, &gravity);
+        let approximate = GravitySolver::BarnesHut { theta: 0.5 }.solve(&bodies, &gravity);
+
+        let (error, total) = exact.iter().zip(&approximate).fold(
+            (0.0, 0.0),
+            |(error, total), (exact, approximate)| {
+                (
+                    error + (exact.force - approximate.force).length_squared(),
+                    total + exact.force.length_squared(),
+                )
+            },
+        );
+        let relative_error = (error / total).sqrt();
+        assert!(relative_error < 0.01, "relative error {relative_error}");
+    }
+
+    #[test]
+    fn zero_opening_angle_is_exact() {
+        let bodies = random_cluster(100);
+        let gravity = GravityConfig::default();
+        let exact = GravitySolver::Pairwise.solve(&bodies, &gravity);
+        let approximate = GravitySolver::BarnesHut { theta: 0.0 }.solve(&bodies, &gravity);
+
+        for (exact, approximate) in exact.iter().zip(&approximate) {
+            let error = (exact.force - approximate.force).length();
+            assert!(error <= 1.0e-3 * exact.force.length());
+            assert_eq!(
+                exact.dominant.map(|(index, _)| index),
+                approximate.dominant.map(|(index, _)| index)
+            );
+        }
+    }
+
+    /// The force should be minus the gradient of the potential, for every law.
+    #[test]
+    fn forces_match_potentials() {
+        let laws = [
+            ForceLaw::Newtonian,
+            ForceLaw::Softened,
+            ForceLaw::Yukawa {
+                strength: 0.5,
+                range: 2.0,
+            },
+            ForceLaw::PowerLaw { exponent: 1.0 },
+            ForceLaw::PowerLaw { exponent: 3.0 },
+            ForceLaw::None,
+        ];
+        for force_law in laws {
+            let gravity = GravityConfig {
+                force_law,
+                ..default()
+            };
+            for distance in [0.5, 1.0, 3.0] {
+                let step = 1.0e-3;
+                let gradient = (gravity.potential(2.0, 3.0, distance + step)
+                    - gravity.potential(2.0, 3.0, distance - step))
+                    / (2.0 * step);
+                let force = gravity.force(2.0, 3.0, Vec2::new(distance, 0.0));
+                assert!(
+                    (force.x - gradient).abs() <= 1.0e-2 * gradient.abs().max(1.0),
+                    "{force_law:?} at {distance}: {} != {gradient}",
+                    force.x
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn softening_keeps_overlapping_bodies_finite() {
+        let gravity = GravityConfig {
+            force_law: ForceLaw::Softened,
+            ..default()
+        };
+        let close = gravity.force(1.0, 1.0, Vec2::new(1.0e-6, 0.0));
+        assert!(close.is_finite() && close.length() < 1.0);
+
+        let far = Vec2::new(100.0, 0.0);
+        let newtonian = GravityConfig::default().force(1.0, 1.0, far);
+        assert!((gravity.force(1.0, 1.0, far) - newtonian).length() < 1.0e-4 * newtonian.length());
+    }
+
+    #[test]
+    fn only_newtonian_gravity_has_keplerian_orbits() {
+        let newtonian = GravityConfig::default();
+        // 0.1 * 10 * 100³
+        let mu = newtonian.gravitational_parameter(10.0, 100.0).unwrap();
+        assert!((mu - 1.0e6).abs() < 1.0, "{mu}");
+
+        let mut force_law = ForceLaw::Newtonian.next();
+        while force_law != ForceLaw::Newtonian {
+            let gravity = GravityConfig {
+                force_law,
+                ..default()
+            };
+            assert_eq!(gravity.gravitational_parameter(10.0, 100.0), None);
+            force_law = force_law.next();
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::celestial_body::{CelestialBody, CelestialBodyPlugin};
+use crate::clock::ClockPlugin;
+use crate::orbital_elements::OrbitalElementsPlugin;
+use crate::scenario::{ActiveScenario, Scenario, ScenarioPlugin};
+use crate::trajectory::{TrajectoryError, TrajectorySample, TrajectoryWriter};
+use crate::units::SimulationUnits;
+
+/// Builds an app that simulates a scenario without a window or renderer.
+///
+/// Every call to `update` advances the simulation by exactly one fixed timestep.
+pub fn headless_app(scenario: Scenario) -> App {
+    let timestep = Duration::from_secs_f32(scenario.settings.timestep);
+
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        AssetPlugin::default(),
+        TransformPlugin,
+        HierarchyPlugin,
+    ))
+    .add_plugins(CelestialBodyPlugin { headless: true })
+    .add_plugins(ClockPlugin { headless: true })
+    .add_plugins(OrbitalElementsPlugin { headless: true })
+    .add_plugins(ScenarioPlugin)
+    .init_resource::<ButtonInput<KeyCode>>()
+    .insert_resource(Time::<Fixed>::from_duration(timestep))
+    .insert_resource(TimeUpdateStrategy::ManualDuration(timestep));
+
+    let handle = app
+        .world_mut()
+        .resource_mut::<Assets<Scenario>>()
+        .add(scenario);
+    app.insert_resource(ActiveScenario::new(handle, PathBuf::new()));
+    app
+}
+
+/// Runs a scenario for a number of fixed steps, streaming every body's state after each step to
+/// `output`.
+pub fn run(scenario: Scenario, steps: u64, output: &Path) -> Result<(), TrajectoryError> {
+    let mut app = headless_app(scenario);
+    let mut writer = TrajectoryWriter::create(output)?;
+
+    // The first update only spawns the bodies, without a fixed step
+    app.update();
+
+    for step in 1..=steps {
+        app.update();
+
+        let world = app.world_mut();
+        let time = world.resource::<Time<Fixed>>().elapsed_seconds();
+        let units = world.resource::<SimulationUnits>().clone();
+        let mut samples: Vec<TrajectorySample> = world
+            .query::<(Entity, &CelestialBody)>()
+            .iter(world)
+            .map(|(entity, body)| TrajectorySample {
+                step,
+                time,
+                body: entity.to_bits(),
+                name: body.name.clone(),
+                position: units.pixels_to_units(body.position),
+                velocity: units.pixels_to_units(body.velocity),
+                mass: body.mass,
+            })
+            .collect();
+        samples.sort_by_key(|sample| sample.body);
+        writer.write(&samples)?;
+    }
+
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::celestial_body::CelestialBody;
+    use crate::clock::SimulationClock;
+    use crate::scenario::BodyDescription;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_scenario(seed: u64) -> Scenario {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let bodies = (0..40)
+            .map(|_| {
+                let body = CelestialBody::default()
+                    .with_position(Vec2::new(
+                        rng.gen_range(-200.0..200.0),
+                        rng.gen_range(-200.0..200.0),
+                    ))
+                    .with_velocity(Vec2::new(
+                        rng.gen_range(-50.0..50.0),
+                        rng.gen_range(-50.0..50.0),
+                    ))
+                    .with_mass(rng.gen_range(0.1..5.0));
+                BodyDescription::new(&body, &default())
+            })
+            .collect();
+        Scenario {
+            bodies,
+            ..default()
+        }
+    }
+
+    /// Runs the scenario and returns the bit patterns of every body's state.
+    fn run(scenario: Scenario, steps: usize) -> Vec<[u32; 5]> {
+        let mut app = headless_app(scenario);
+        for _ in 0..steps {
+            app.update();
+        }
+
+        let world = app.world_mut();
+        let mut bodies: Vec<(Entity, CelestialBody)> = world
+            .query::<(Entity, &CelestialBody)>()
+            .iter(world)
+            .map(|(entity, body)| (entity, body.clone()))
+            .collect();
+        bodies.sort_by_key(|(entity, _)| *entity);
+        bodies
+            .iter()
+            .map(|(_, body)| {
+                [
+                    body.position.x.to_bits(),
+                    body.position.y.to_bits(),
+                    body.velocity.x.to_bits(),
+                    body.velocity.y.to_bits(),
+                    body.mass.to_bits(),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replays_are_bit_identical() {
+        let steps = 300;
+        let first = run(random_scenario(7), steps);
+        let second = run(random_scenario(7), steps);
+
+        // Make sure some bodies merged, so merge ordering is exercised too
+        assert!(first.len() < 40, "no bodies merged");
+        assert_eq!(first, second);
+    }
+
+    fn positions(app: &mut App) -> Vec<Vec2> {
+        let world = app.world_mut();
+        let mut bodies: Vec<(Entity, Vec2)> = world
+            .query::<(Entity, &CelestialBody)>()
+            .iter(world)
+            .map(|(entity, body)| (entity, body.position))
+            .collect();
+        bodies.sort_by_key(|(entity, _)| *entity);
+        bodies.into_iter().map(|(_, position)| position).collect()
+    }
+
+    #[test]
+    fn reversing_retraces_the_orbits() {
+        // A star with two planets on well separated orbits, so nothing collides
+        let bodies = [
+            CelestialBody::default().with_mass(10.0),
+            CelestialBody::default()
+                .with_position(Vec2::new(150.0, 0.0))
+                .with_velocity(Vec2::new(0.0, 90.0)),
+            CelestialBody::default()
+                .with_position(Vec2::new(0.0, -300.0))
+                .with_velocity(Vec2::new(60.0, 0.0)),
+        ];
+        let mut app = headless_app(Scenario {
+            bodies: bodies
+                .iter()
+                .map(|body| BodyDescription::new(body, &default()))
+                .collect(),
+            ..default()
+        });
+
+        // The first update only spawns the bodies
+        app.update();
+        let start = positions(&mut app);
+
+        let steps = 200;
+        for _ in 0..steps {
+            app.update();
+        }
+        assert!(positions(&mut app)[1].distance(start[1]) > 10.0);
+
+        app.world_mut().resource_mut::<SimulationClock>().reversed = true;
+        for _ in 0..steps {
+            app.update();
+        }
+
+        for (end, start) in positions(&mut app).iter().zip(&start) {
+            assert!(end.distance(*start) < 1.0e-2, "{end} != {start}");
+        }
+        let clock = app.world().resource::<SimulationClock>();
+        assert!(clock.elapsed_seconds().abs() < 1.0e-9);
+    }
+
+    #[test]
+    fn writes_every_step_after_it_is_taken() {
+        let scenario = random_scenario(3);
+        let timestep = scenario.settings.timestep;
+        let steps = 10;
+        let path = std::env::temp_dir().join(format!(
+            "bevy_solar_system_{}_headless.csv",
+            std::process::id()
+        ));
+        super::run(scenario, steps, &path).unwrap();
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rows: Vec<Vec<&str>> = csv
+            .lines()
+            .skip(1)
+            .map(|row| row.split(',').collect())
+            .collect();
+        let first = &rows[0];
+        let last = rows.last().unwrap();
+        assert_eq!(first[0], "1");
+        assert_eq!(first[1].parse::<f32>().unwrap(), timestep);
+        assert_eq!(last[0], steps.to_string());
+        assert_eq!(last[1].parse::<f32>().unwrap(), steps as f32 * timestep);
+    }
+}
@@ -0,0 +1,386 @@
+use bevy::prelude::*;
+
+/// Selects the symplectic scheme used to advance the bodies each fixed step.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Integrator {
+    /// Second order kick-drift-kick leapfrog, also known as velocity Verlet.
+    #[default]
+    VelocityVerlet,
+    /// Fourth order Yoshida integrator, built from three velocity Verlet substeps.
+    Yoshida4,
+}
+
+/// Substep weights of the Yoshida "triple jump".
+const YOSHIDA_W1: f64 = 1.0 / (2.0 - 1.259_921_049_894_873_2);
+const YOSHIDA_W0: f64 = -1.259_921_049_894_873_2 * YOSHIDA_W1;
+
+impl Integrator {
+    /// Advances the bodies by `dt` seconds, starting from the accelerations at the current
+    /// positions, which are left holding the accelerations at the final positions.
+    ///
+    /// `acceleration` returns the acceleration of every body for the given positions. It is called
+    /// last with the final positions, so any side results it records describe the end of the step.
+    fn step_from(
+        &self,
+        positions: &mut [Vec2],
+        velocities: &mut [Vec2],
+        accelerations: &mut Vec<Vec2>,
+        dt: f32,
+        acceleration: &mut impl FnMut(&[Vec2]) -> Vec<Vec2>,
+    ) {
+        match self {
+            Self::VelocityVerlet => {
+                velocity_verlet(positions, velocities, accelerations, dt, acceleration)
+            }
+            Self::Yoshida4 => {
+                for weight in [YOSHIDA_W1, YOSHIDA_W0, YOSHIDA_W1] {
+                    velocity_verlet(
+                        positions,
+                        velocities,
+                        accelerations,
+                        (weight * dt as f64) as f32,
+                        acceleration,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// The acceleration of every body, with the index of the body that pulls it hardest.
+pub type Pull = (Vec<Vec2>, Vec<Option<usize>>);
+
+/// Splits the fixed step into substeps during close encounters.
+///
+/// Each body is checked against the body that pulls it hardest, which the gravity solver finds
+/// anyway, and the number of substeps is picked so that no such pair closes more than `accuracy`
+/// of their separation in one substep. Counts are powers of two, and a step that ends needing more
+/// substeps than it took is taken again with more, so running time backwards from the end picks
+/// the same count and retraces the step.
+#[derive(Resource, Clone, Debug)]
+pub struct AdaptiveTimestep {
+    pub enabled: bool,
+    pub accuracy: f32,
+    pub max_substeps: u32,
+    /// Substeps taken by the latest fixed step.
+    last_substeps: u32,
+}
+
+impl Default for AdaptiveTimestep {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            accuracy: 0.05,
+            max_substeps: 64,
+            last_substeps: 1,
+        }
+    }
+}
+
+impl AdaptiveTimestep {
+    /// How many substeps a step of `dt` needs, from the distance over relative speed of each body
+    /// and the `partner` pulling it hardest.
+    pub fn substeps(
+        &self,
+        positions: &[Vec2],
+        velocities: &[Vec2],
+        partners: &[Option<usize>],
+        dt: f32,
+    ) -> u32 {
+        if !self.enabled {
+            return 1;
+        }
+
+        let timescale = partners
+            .iter()
+            .enumerate()
+            .filter_map(|(i, partner)| {
+                let j = (*partner)?;
+                let speed = velocities[i].distance(velocities[j]);
+                (speed > 0.0).then(|| positions[i].distance(positions[j]) / speed)
+            })
+            .fold(f32::INFINITY, f32::min);
+
+        // An infinite timescale gives zero, which is clamped up to a single step
+        let substeps = (dt.abs() / (self.accuracy * timescale)).ceil();
+        let substeps = substeps.clamp(1.0, self.max_substeps as f32) as u32;
+        substeps.next_power_of_two().min(self.max_substeps)
+    }
+
+    /// Advances the bodies by `dt` seconds in as many substeps as the encounters need.
+    ///
+    /// `pull` is called as the integrator's acceleration, and last with the final positions.
+    pub fn step(
+        &mut self,
+        integrator: Integrator,
+        positions: &mut [Vec2],
+        velocities: &mut [Vec2],
+        dt: f32,
+        mut pull: impl FnMut(&[Vec2]) -> Pull,
+    ) {
+        let (start_accelerations, start_partners) = pull(positions);
+        let mut substeps = self.substeps(positions, velocities, &start_partners, dt);
+        let start = self
+            .enabled
+            .then(|| (positions.to_vec(), velocities.to_vec()));
+
+        loop {
+            let mut accelerations = start_accelerations.clone();
+            let mut partners = start_partners.clone();
+            let mut acceleration = |positions: &[Vec2]| {
+                let (accelerations, latest) = pull(positions);
+                partners = latest;
+                accelerations
+            };
+            for _ in 0..substeps {
+                integrator.step_from(
+                    positions,
+                    velocities,
+                    &mut accelerations,
+                    dt / substeps as f32,
+                    &mut acceleration,
+                );
+            }
+
+            let needed = self.substeps(positions, velocities, &partners, dt);
+            let Some((start_positions, start_velocities)) =
+                start.as_ref().filter(|_| needed > substeps)
+            else {
+                break;
+            };
+            positions.copy_from_slice(start_positions);
+            velocities.copy_from_slice(start_velocities);
+            substeps = needed;
+        }
+        self.last_substeps = substeps;
+    }
+
+    pub fn last_substeps(&self) -> u32 {
+        self.last_substeps
+    }
+}
+
+/// A single kick-drift-kick step, reusing the accelerations at the current positions.
+fn velocity_verlet(
+    positions: &mut [Vec2],
+    velocities: &mut [Vec2],
+    accelerations: &mut Vec<Vec2>,
+    dt: f32,
+    acceleration: &mut impl FnMut(&[Vec2]) -> Vec<Vec2>,
+) {
+    for ((position, velocity), accel) in positions
+        .iter_mut()
+        .zip(velocities.iter_mut())
+        .zip(accelerations.iter())
+    {
+        *velocity += 0.5 * dt * *accel;
+        *position += dt * *velocity;
+    }
+
+    *accelerations = acceleration(positions);
+
+    for (velocity, accel) in velocities.iter_mut().zip(accelerations.iter()) {
+        *velocity += 0.5 * dt * *accel;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gravity::{GravityConfig, GravitySolver, PointMass};
+
+    const G: f32 = 10.0;
+
+    fn accelerations(masses: &[f32], positions: &[Vec2]) -> Vec<Vec2> {
+        let bodies: Vec<PointMass> = positions
+            .iter()
+            .zip(masses)
+            .map(|(&position, &mass)| PointMass { position, mass })
+            .collect();
+        GravitySolver::Pairwise
+            .solve(
+                &bodies,
+                &GravityConfig {
+                    gravitational_constant: G,
+                    ..default()
+                },
+            )
+            .iter()
+            .zip(masses)
+            .map(|(attraction, mass)| attraction.force / *mass)
+            .collect()
+    }
+
+    /// The accelerations of a pair, which pull hardest on each other.
+    fn pull(masses: &[f32], positions: &[Vec2]) -> Pull {
+        (accelerations(masses, positions), vec![Some(1), Some(0)])
+    }
+
+    fn energy(masses: &[f32], positions: &[Vec2], velocities: &[Vec2]) -> f32 {
+        let kinetic: f32 = masses
+            .iter()
+            .zip(velocities)
+            .map(|(mass, velocity)| 0.5 * mass * velocity.length_squared())
+            .sum();
+        let potential = -G * masses[0] * masses[1] / positions[0].distance(positions[1]);
+        kinetic + potential
+    }
+
+    fn circular_orbit_energy_drift(integrator: Integrator) -> f32 {
+        let masses = [10.0, 1.0];
+        let total_mass: f32 = masses.iter().sum();
+        let separation = 1.0;
+
+        // Place both bodies about the centre of mass with the circular relative speed
+        let speed = (G * total_mass / separation).sqrt();
+        let mut positions = [
+            Vec2::new(-separation * masses[1] / total_mass, 0.0),
+            Vec2::new(separation * masses[0] / total_mass, 0.0),
+        ];
+        let mut velocities = [
+            Vec2::new(0.0, -speed * masses[1] / total_mass),
+            Vec2::new(0.0, speed * masses[0] / total_mass),
+        ];
+
+        let period = 2.0 * std::f32::consts::PI * (separation.powi(3) / (G * total_mass)).sqrt();
+        let dt = period / 200.0;
+
+        let initial = energy(&masses, &positions, &velocities);
+        let mut drift: f32 = 0.0;
+        let mut current_accelerations = accelerations(&masses, &positions);
+        for _ in 0..10_000 {
+            integrator.step_from(
+                &mut positions,
+                &mut velocities,
+                &mut current_accelerations,
+                dt,
+                &mut |positions| accelerations(&masses, positions),
+            );
+            let current = energy(&masses, &positions, &velocities);
+            drift = drift.max(((current - initial) / initial).abs());
+        }
+        drift
+    }
+
+    #[test]
+    fn velocity_verlet_conserves_energy() {
+        let drift = circular_orbit_energy_drift(Integrator::VelocityVerlet);
+        assert!(drift < 1.0e-3, "relative energy drift {drift}");
+    }
+
+    #[test]
+    fn yoshida_conserves_energy() {
+        let drift = circular_orbit_energy_drift(Integrator::Yoshida4);
+        assert!(drift < 1.0e-4, "relative energy drift {drift}");
+    }
+
+    /// Relative energy error after a fast, close hyperbolic flyby.
+    fn flyby_energy_error(adaptive: &mut AdaptiveTimestep) -> f32 {
+        let masses = [10.0, 1.0];
+        let mut positions = [Vec2::ZERO, Vec2::new(-2.0, 0.2)];
+        let mut velocities = [Vec2::ZERO, Vec2::new(20.0, 0.0)];
+
+        let initial = energy(&masses, &positions, &velocities);
+        let mut max_substeps = 0;
+        for _ in 0..40 {
+            adaptive.step(
+                Integrator::VelocityVerlet,
+                &mut positions,
+                &mut velocities,
+                0.01,
+                |positions| pull(&masses, positions),
+            );
+            max_substeps = max_substeps.max(adaptive.last_substeps());
+        }
+        let separation = positions[1] - positions[0];
+        assert!(
+            separation.dot(velocities[1] - velocities[0]) > 0.0,
+            "the flyby should have happened"
+        );
+        if adaptive.enabled {
+            assert!(max_substeps > 1);
+        }
+        ((energy(&masses, &positions, &velocities) - initial) / initial).abs()
+    }
+
+    #[test]
+    fn substepping_resolves_close_encounters() {
+        let fixed = flyby_energy_error(&mut AdaptiveTimestep {
+            enabled: false,
+            ..default()
+        });
+        let adaptive = flyby_energy_error(&mut AdaptiveTimestep {
+            max_substeps: 1024,
+            ..default()
+        });
+        assert!(adaptive < 1.0e-2, "adaptive energy error {adaptive}");
+        assert!(adaptive < 0.1 * fixed, "{adaptive} vs fixed {fixed}");
+    }
+
+    #[test]
+    fn distant_bodies_take_a_single_step() {
+        let adaptive = AdaptiveTimestep::default();
+        let positions = [Vec2::ZERO, Vec2::new(1000.0, 0.0)];
+        let partners = [Some(1), Some(0)];
+        let velocities = [Vec2::ZERO, Vec2::new(0.0, 10.0)];
+        assert_eq!(
+            adaptive.substeps(&positions, &velocities, &partners, 1.0 / 64.0),
+            1
+        );
+
+        // Closing in, rounded up to a power of two
+        let velocities = [Vec2::ZERO, Vec2::new(-9000.0, 0.0)];
+        assert_eq!(
+            adaptive.substeps(&positions, &velocities, &partners, 1.0 / 64.0),
+            1 << 2
+        );
+
+        // Head on and very close, capped at the maximum
+        let velocities = [Vec2::ZERO, Vec2::new(-1.0e6, 0.0)];
+        assert_eq!(
+            adaptive.substeps(&positions, &velocities, &partners, 1.0 / 64.0),
+            64
+        );
+    }
+
+    #[test]
+    fn reversing_retraces_the_substeps() {
+        let masses = [10.0, 1.0];
+        let start = (
+            [Vec2::ZERO, Vec2::new(-2.0, 0.2)],
+            [Vec2::ZERO, Vec2::new(20.0, 0.0)],
+        );
+        let (mut positions, mut velocities) = start;
+        let mut adaptive = AdaptiveTimestep::default();
+
+        let mut forwards = Vec::new();
+        for _ in 0..40 {
+            adaptive.step(
+                Integrator::VelocityVerlet,
+                &mut positions,
+                &mut velocities,
+                0.01,
+                |positions| pull(&masses, positions),
+            );
+            forwards.push(adaptive.last_substeps());
+        }
+        let mut backwards = Vec::new();
+        for _ in 0..40 {
+            adaptive.step(
+                Integrator::VelocityVerlet,
+                &mut positions,
+                &mut velocities,
+                -0.01,
+                |positions| pull(&masses, positions),
+            );
+            backwards.push(adaptive.last_substeps());
+        }
+
+        backwards.reverse();
+        assert_eq!(forwards, backwards);
+        assert!(forwards.iter().any(|&substeps| substeps > 1));
+        for (end, start) in positions.iter().zip(start.0) {
+            assert!(end.distance(start) < 1.0e-3, "{end} != {start}");
+        }
+    }
+}
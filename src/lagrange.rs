@@ -0,0 +1,244 @@
+use bevy::prelude::*;
+
+use crate::celestial_body::{CelestialBody, TwoBodyProblem};
+use crate::frame::FramePose;
+use crate::selection::Selected;
+use crate::MainCamera;
+
+pub struct LagrangePlugin;
+impl Plugin for LagrangePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LagrangeOverlay>()
+            .add_systems(Update, (toggle_lagrange_overlay, draw_lagrange_overlay));
+    }
+}
+
+/// Whether to draw the Lagrange points, Hill sphere and Roche limit of the selected body.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct LagrangeOverlay {
+    pub enabled: bool,
+}
+
+/// Coefficient of the Roche limit of a fluid satellite, which deforms as it is pulled apart.
+const ROCHE_COEFFICIENT: f32 = 2.44;
+
+/// Bisection steps when solving for the collinear points, enough for f32 precision.
+const BISECTION_STEPS: usize = 64;
+
+/// The Lagrange points of a pair, and how close the secondary can get to the primary.
+///
+/// Worked out for a circular orbit at the pair's current separation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LagrangePoints {
+    /// L1 to L5, in world pixels.
+    pub points: [Vec2; 5],
+    /// Radius of the secondary's Hill sphere, in pixels.
+    pub hill_radius: f32,
+    /// Distance from the primary inside which the secondary is torn apart, in pixels.
+    pub roche_limit: f32,
+}
+
+impl LagrangePoints {
+    pub fn new(primary: &CelestialBody, secondary: &CelestialBody) -> Self {
+        let separation = primary.position.distance(secondary.position);
+        let mass_ratio = secondary.mass / (primary.mass + secondary.mass);
+        let pose = FramePose::co_rotating(
+            (primary.position, primary.mass),
+            (secondary.position, secondary.mass),
+        );
+
+        let [l1, l2, l3] = collinear_points(mass_ratio);
+        let triangular = Vec2::new(0.5 - mass_ratio, 3.0_f32.sqrt() / 2.0);
+        let points = [
+            Vec2::new(l1, 0.0),
+            Vec2::new(l2, 0.0),
+            Vec2::new(l3, 0.0),
+            triangular,
+            Vec2::new(triangular.x, -triangular.y),
+        ]
+        .map(|point| pose.to_world(point * separation));
+
+        Self {
+            points,
+            hill_radius: separation * (secondary.mass / (3.0 * primary.mass)).cbrt(),
+            roche_limit: roche_limit(primary, secondary),
+        }
+    }
+}
+
+/// The fluid Roche limit, treating each body as a sphere of its own radius.
+///
+/// With densities `m / r³`, `2.44 R (ρ_M / ρ_m)^⅓` comes down to `2.44 r (M / m)^⅓`.
+fn roche_limit(primary: &CelestialBody, secondary: &CelestialBody) -> f32 {
+    ROCHE_COEFFICIENT * secondary.radius() * (primary.mass / secondary.mass).cbrt()
+}
+
+/// L1, L2 and L3 along the x axis of the co-rotating frame, in units of the separation.
+///
+/// The barycentre is at the origin, the primary at `-mass_ratio` and the secondary at
+/// `1 - mass_ratio`, where `mass_ratio` is the secondary's share of the total mass.
+fn collinear_points(mass_ratio: f32) -> [f32; 3] {
+    let mu = mass_ratio as f64;
+    let (primary, secondary) = (-mu, 1.0 - mu);
+
+    // Gravity of both bodies plus the centrifugal force, along the axis
+    let force = |x: f64| {
+        let pull = |at: f64, mass: f64| mass * (x - at) / (x - at).abs().powi(3);
+        x - pull(primary, 1.0 - mu) - pull(secondary, mu)
+    };
+    let solve = |mut low: f64, mut high: f64| {
+        let rising = force(high) > force(low);
+        for _ in 0..BISECTION_STEPS {
+            let middle = (low + high) / 2.0;
+            if (force(middle) > 0.0) == rising {
+                high = middle;
+            } else {
+                low = middle;
+            }
+        }
+        ((low + high) / 2.0) as f32
+    };
+
+    // Each point sits alone on its stretch of the axis, between the singularities at the bodies
+    let near = 1.0e-9;
+    [
+        solve(primary + near, secondary - near),
+        solve(secondary + near, secondary + 2.0),
+        solve(primary - 2.0, primary - near),
+    ]
+}
+
+/// Turns the overlay on and off when H is pressed
+fn toggle_lagrange_overlay(keys: Res<ButtonInput<KeyCode>>, mut overlay: ResMut<LagrangeOverlay>) {
+    if keys.just_pressed(KeyCode::KeyH) {
+        overlay.enabled = !overlay.enabled;
+    }
+}
+
+/// Size of the Lagrange point markers, in pixels on screen.
+const MARKER_SIZE: f32 = 5.0;
+
+/// Marks L1 to L5 of the selected body and its primary, circles its Hill sphere and circles the
+/// Roche limit around the primary
+fn draw_lagrange_overlay(
+    mut gizmos: Gizmos,
+    overlay: Res<LagrangeOverlay>,
+    camera: Query<&OrthographicProjection, With<MainCamera>>,
+    selected: Query<(&CelestialBody, &TwoBodyProblem), With<Selected>>,
+    bodies: Query<&CelestialBody>,
+) {
+    if !overlay.enabled {
+        return;
+    }
+    let Ok((body, two_body_problem)) = selected.get_single() else {
+        return;
+    };
+    let Some(other) = two_body_problem
+        .entity
+        .and_then(|entity| bodies.get(entity).ok())
+    else {
+        return;
+    };
+    let (primary, secondary) = if body.mass > other.mass {
+        (body, other)
+    } else {
+        (other, body)
+    };
+
+    let lagrange = LagrangePoints::new(primary, secondary);
+    let scale = camera
+        .get_single()
+        .map_or(1.0, |projection| projection.scale);
+    let marker = MARKER_SIZE * scale;
+    let color = Color::srgb(0.4, 1.0, 0.6);
+    for point in lagrange.points {
+        gizmos.line_2d(point - Vec2::X * marker, point + Vec2::X * marker, color);
+        gizmos.line_2d(point - Vec2::Y * marker, point + Vec2::Y * marker, color);
+    }
+    gizmos.circle_2d(secondary.position, lagrange.hill_radius, color);
+    gizmos.circle_2d(
+        primary.position,
+        lagrange.roche_limit,
+        Color::srgb(1.0, 0.4, 0.3),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(mass_ratio: f32) -> (CelestialBody, CelestialBody) {
+        let primary = CelestialBody::default()
+            .with_mass(1.0 - mass_ratio)
+            .with_position(Vec2::new(-100.0 * mass_ratio, 0.0));
+        let secondary = CelestialBody::default()
+            .with_mass(mass_ratio)
+            .with_position(Vec2::new(100.0 * (1.0 - mass_ratio), 0.0));
+        (primary, secondary)
+    }
+
+    #[test]
+    fn l1_and_l2_sit_a_hill_radius_from_the_secondary() {
+        // Small enough for the approximation `r (μ / 3)^⅓`, which is good to about a third of itself
+        for mass_ratio in [1.0e-6, 1.0e-4, 9.5e-4] {
+            let [l1, l2, _] = collinear_points(mass_ratio);
+            let secondary = 1.0 - mass_ratio;
+            let approximation = (mass_ratio / 3.0).cbrt();
+            let tolerance = approximation * approximation / 2.0;
+            assert!(
+                (secondary - l1 - approximation).abs() < tolerance,
+                "L1 at {l1} for μ = {mass_ratio}"
+            );
+            assert!(
+                (l2 - secondary - approximation).abs() < tolerance,
+                "L2 at {l2} for μ = {mass_ratio}"
+            );
+        }
+    }
+
+    #[test]
+    fn l3_sits_opposite_the_secondary() {
+        // `-(1 + 5μ / 12)` to first order
+        let mass_ratio = 1.0e-3;
+        let [_, _, l3] = collinear_points(mass_ratio);
+        assert!((l3 + 1.0 + 5.0 * mass_ratio / 12.0).abs() < 1.0e-5, "{l3}");
+    }
+
+    #[test]
+    fn equal_masses_are_symmetric() {
+        let [l1, l2, l3] = collinear_points(0.5);
+        assert!(l1.abs() < 1.0e-6);
+        assert!((l2 + l3).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn points_follow_the_pair() {
+        let (primary, secondary) = pair(1.0e-3);
+        let lagrange = LagrangePoints::new(&primary, &secondary);
+
+        // L4 and L5 make equilateral triangles with the pair
+        for point in &lagrange.points[3..] {
+            assert!((point.distance(primary.position) - 100.0).abs() < 1.0e-3);
+            assert!((point.distance(secondary.position) - 100.0).abs() < 1.0e-3);
+        }
+        // L1 is at the edge of the Hill sphere
+        let l1 = lagrange.points[0].distance(secondary.position);
+        assert!((l1 - lagrange.hill_radius).abs() < 0.1 * lagrange.hill_radius);
+    }
+
+    #[test]
+    fn roche_limit_of_the_moon() {
+        // Radii in thousands of km and masses in Moon masses
+        let earth = CelestialBody {
+            density: 81.3 / (std::f32::consts::PI * 6.371 * 6.371),
+            ..CelestialBody::default().with_mass(81.3)
+        };
+        let moon = CelestialBody {
+            density: 1.0 / (std::f32::consts::PI * 1.737 * 1.737),
+            ..CelestialBody::default()
+        };
+        // The fluid limit for the Earth and Moon is about 18 thousand km
+        let limit = roche_limit(&earth, &moon);
+        assert!((limit - 18.4).abs() < 0.2, "{limit}");
+    }
+}
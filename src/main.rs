@@ -1,31 +1,144 @@
+mod camera;
 mod celestial_body;
+mod clock;
+mod collision;
+mod field;
+mod frame;
+mod generator;
+mod gravity;
+mod headless;
+mod integrator;
+mod lagrange;
+mod orbital_elements;
+mod prediction;
+mod scenario;
+mod selection;
+mod solar_system;
 mod stats;
 mod trails;
+mod trajectory;
+mod units;
 
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
-use bevy_rapier2d::prelude::*;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
 
-use celestial_body::{
-    add_celestial_body, add_sprite, CelestialBody, CelestialBodyAssets, CelestialBodyPlugin,
-};
+use camera::CameraPlugin;
+use celestial_body::{CelestialBody, CelestialBodyPlugin};
+use clock::ClockPlugin;
+use field::FieldPlugin;
+use frame::FramePlugin;
+use generator::{random_direction, Generator};
+use lagrange::LagrangePlugin;
+use orbital_elements::OrbitalElementsPlugin;
+use prediction::PredictionPlugin;
+use scenario::{ActiveScenario, BodyDescription, Scenario, ScenarioPlugin, ScenarioSettings};
 use trails::{Trail, TrailsPlugin};
 
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
+use selection::SelectionPlugin;
 use stats::StatsPlugin;
-use std::ops::Range;
+use std::path::{Path, PathBuf};
+use trajectory::TrajectoryError;
+
+/// Command line arguments
+#[derive(Parser, Resource, Clone, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Scenario to load, relative to the assets folder (e.g. scenarios/three_body.scenario.ron)
+    #[arg(long)]
+    scenario: Option<String>,
+
+    /// Built-in scenario to run instead of the default one
+    #[arg(long, value_enum, conflicts_with = "scenario")]
+    builtin: Option<BuiltinScenario>,
+
+    /// Where F5 saves the live world, relative to the assets folder like --scenario, as RON or JSON
+    /// depending on the extension
+    #[arg(long, default_value = "saved.scenario.ron")]
+    save: PathBuf,
+
+    /// Seed for the randomly placed bodies of the default and generated scenarios
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Number of bodies in a generated scenario, instead of the preset's own
+    #[arg(long, requires = "builtin")]
+    count: Option<usize>,
+
+    /// Run without a window and write the trajectories to --output
+    #[arg(long)]
+    headless: bool,
+
+    /// Number of fixed steps to simulate in headless mode
+    #[arg(long, default_value_t = 1000)]
+    steps: u64,
+
+    /// Trajectory file written in headless mode, as CSV or Parquet depending on the extension
+    #[arg(long, default_value = "trajectory.csv")]
+    output: PathBuf,
+}
+
+/// Scenarios that are set up in code rather than loaded from a file
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum BuiltinScenario {
+    /// The Sun, planets and major moons at J2000
+    SolarSystem,
+    /// A protoplanetary disk around a star
+    Disk,
+    /// A Plummer sphere star cluster
+    Plummer,
+    /// A binary star with circumbinary planets
+    Binary,
+    /// Two colliding disk galaxies
+    Galaxies,
+}
 
 /// The main function of the game
 fn main() {
+    let args = Args::parse();
+    if args.count.is_some() && matches!(args.builtin, Some(BuiltinScenario::SolarSystem)) {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--count only applies to generated scenarios, not the solar system",
+            )
+            .exit();
+    }
+
+    if args.headless {
+        let result = headless_scenario(&args)
+            .and_then(|scenario| headless::run(scenario, args.steps, &args.output));
+        match result {
+            Ok(()) => println!("Wrote {} steps to {}", args.steps, args.output.display()),
+            Err(error) => {
+                eprintln!("Headless run failed: {error}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     let mut app = App::new();
     app.add_plugins(DefaultPlugins)
-        .add_plugins(CelestialBodyPlugin)
+        .add_plugins(CelestialBodyPlugin::default())
+        .add_plugins(ClockPlugin { headless: false })
         .add_plugins(TrailsPlugin)
+        .add_plugins(FramePlugin)
+        .add_plugins(PredictionPlugin)
+        .add_plugins(OrbitalElementsPlugin { headless: false })
+        .add_plugins(SelectionPlugin)
+        .add_plugins(LagrangePlugin)
+        .add_plugins(FieldPlugin)
+        .add_plugins(ScenarioPlugin)
+        .insert_resource(args)
         .add_systems(Startup, setup);
 
     app.add_plugins(StatsPlugin);
 
-    app.add_systems(Update, pan_camera);
+    app.add_plugins(CameraPlugin);
 
     app.run();
 }
@@ -33,126 +146,120 @@ fn main() {
 #[derive(Component)]
 struct MainCamera;
 
+/// Where the cursor points in the world, as seen through the main camera.
+#[derive(SystemParam)]
+struct WorldCursor<'w, 's> {
+    windows: Query<'w, 's, &'static Window>,
+    camera: Query<'w, 's, (&'static Camera, &'static GlobalTransform), With<MainCamera>>,
+    interactions: Query<'w, 's, &'static Interaction>,
+}
+
+impl WorldCursor<'_, '_> {
+    fn position(&self) -> Option<Vec2> {
+        let (camera, camera_transform) = self.camera.single();
+        self.windows
+            .single()
+            .cursor_position()
+            .and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor))
+    }
+
+    /// Whether the cursor is over a button or other interactive UI.
+    fn over_ui(&self) -> bool {
+        self.interactions
+            .iter()
+            .any(|interaction| *interaction != Interaction::None)
+    }
+}
+
 /// Sets up the N-body simulation
 fn setup(
     mut commands: Commands,
-    mut rapier_config: ResMut<RapierConfiguration>,
-    rapier_context: Res<RapierContext>,
-    celestial_body_assets: Res<CelestialBodyAssets>,
+    args: Res<Args>,
+    asset_server: Res<AssetServer>,
+    mut scenarios: ResMut<Assets<Scenario>>,
 ) {
-    assert!(
-        rapier_context.integration_parameters.length_unit >= 1.0,
-        "pixels_per_meter must be >= 1.0"
-    );
-
-    // Set gravity to zero for a space-like environment
-    rapier_config.gravity = Vec2::ZERO;
-
     // Camera
     commands
         .spawn(Camera2dBundle { ..default() })
         .insert(MainCamera);
 
+    let handle = match &args.scenario {
+        Some(path) => asset_server.load(path.clone()),
+        None => scenarios.add(builtin_scenario(&args)),
+    };
+    commands.insert_resource(ActiveScenario::new(handle, args.save.clone()));
+}
+
+/// Reads the scenario for a headless run, which has no asset server to load it with
+fn headless_scenario(args: &Args) -> Result<Scenario, TrajectoryError> {
+    match &args.scenario {
+        Some(path) => Ok(Scenario::read(Path::new(path))?),
+        None => Ok(builtin_scenario(args)),
+    }
+}
+
+/// The scenario to run when none is loaded from a file
+fn builtin_scenario(args: &Args) -> Scenario {
+    let generator = match args.builtin {
+        Some(BuiltinScenario::SolarSystem) => return solar_system::scenario(),
+        Some(BuiltinScenario::Disk) => Generator::disk(),
+        Some(BuiltinScenario::Plummer) => Generator::plummer(),
+        Some(BuiltinScenario::Binary) => Generator::binary(),
+        Some(BuiltinScenario::Galaxies) => Generator::galaxies(),
+        None => return default_scenario(args.seed),
+    };
+    let generator = generator.with_seed(args.seed);
+    let generator = match args.count {
+        Some(count) => generator.with_count(count),
+        None => generator,
+    };
+
+    Scenario {
+        settings: ScenarioSettings {
+            zoom_presets: vec![1.2 * generator.extent()],
+            ..default()
+        },
+        generator: Some(generator),
+        ..default()
+    }
+}
+
+/// The scenario used when none is given on the command line
+fn default_scenario(seed: u64) -> Scenario {
     // Create bodies at know positions
-    let entity = commands.spawn_empty().id();
-
-    let mass = 10.0;
-    add_sprite(&mut commands, entity, &celestial_body_assets, mass);
-    add_celestial_body(
-        &mut commands,
-        entity,
-        CelestialBody::default().with_mass(mass),
-    );
-    commands.entity(entity).insert(Trail::default());
-
-    let entity = commands.spawn_empty().id();
-    let mass = 5.0;
-    add_sprite(&mut commands, entity, &celestial_body_assets, mass);
-    add_celestial_body(
-        &mut commands,
-        entity,
+    let mut bodies = vec![
+        CelestialBody::default().with_mass(10.0),
         CelestialBody::default()
             .with_position(Vec2::new(-100f32, 0f32))
             .with_velocity(Vec2::new(60.0, 60.0))
-            .with_mass(mass),
-    );
-    commands.entity(entity).insert(Trail::default());
-
-    let entity = commands.spawn_empty().id();
-    let mass = 5.0;
-    add_sprite(&mut commands, entity, &celestial_body_assets, mass);
-    add_celestial_body(
-        &mut commands,
-        entity,
+            .with_mass(5.0),
         CelestialBody::default()
             .with_position(Vec2::new(100f32, 0f32))
             .with_velocity(Vec2::new(-100.0, -100.0))
-            .with_mass(mass),
-    );
-    commands.entity(entity).insert(Trail::default());
+            .with_mass(5.0),
+    ];
 
-    // FIXME: Why is this not determinstic?
     // Add some bodies at random positions
-    let seed: [u8; 32] = [0; 32];
-    let mut rng = StdRng::from_seed(seed);
+    let mut seed_bytes = [0; 32];
+    seed_bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    let mut rng = StdRng::from_seed(seed_bytes);
 
     for _ in 0..10 {
         let mass = rng.gen_range(0.1..1.0) as f32;
-        let entity = commands.spawn_empty().id();
-        add_sprite(&mut commands, entity, &celestial_body_assets, mass);
-        add_celestial_body(
-            &mut commands,
-            entity,
-            CelestialBody {
-                position: Vec2::gen_from_range(&mut rng, -400.0..400.0),
-                velocity: Vec2::gen_from_range(&mut rng, -50.0..50.0),
-                mass,
-            },
+        bodies.push(
+            CelestialBody::default()
+                // The square root keeps the bodies evenly spread over the disk
+                .with_position(random_direction(&mut rng) * 400.0 * rng.gen::<f32>().sqrt())
+                .with_velocity(random_direction(&mut rng) * rng.gen_range(0.0..50.0))
+                .with_mass(mass),
         );
-        commands.entity(entity).insert(Trail::default());
     }
-}
 
-/// Helper trait for generating random numbers for different types
-trait MyRand {
-    fn gen_from_range(g: &mut StdRng, range: Range<f32>) -> Self;
-}
-
-// FIXME: Should be a random direction
-impl MyRand for Vec2 {
-    fn gen_from_range(g: &mut StdRng, range: Range<f32>) -> Self {
-        Vec2::new(g.gen_range(range.clone()), g.gen_range(range.clone()))
+    Scenario {
+        bodies: bodies
+            .iter()
+            .map(|body| BodyDescription::new(body, &default()))
+            .collect(),
+        ..default()
     }
 }
-
-const EDGE_THRESHOLD: f32 = 40.0;
-const CAMERA_SPEED: f32 = 10.0;
-
-fn pan_camera(
-    mut camera_q: Query<(&Camera, &GlobalTransform, &mut Transform), With<MainCamera>>,
-    windows_q: Query<&Window>,
-) {
-    let window = windows_q.single();
-    let (camera, camera_global_transform, mut camera_transform) = camera_q.single_mut();
-
-    if let Some(mouse_position) = windows_q.single().cursor_position() {
-        let radius = (window.width() / 2.0).min(window.height() / 2.0) - EDGE_THRESHOLD;
-
-        let window_center = Vec2::new(window.width() / 2.0, window.height() / 2.0);
-        let position_from_center = mouse_position - window_center;
-
-        if position_from_center.length() < radius {
-            return;
-        }
-
-        let t1 = camera
-            .viewport_to_world_2d(camera_global_transform, mouse_position)
-            .unwrap();
-        let t2 = camera
-            .viewport_to_world_2d(camera_global_transform, window_center)
-            .unwrap();
-
-        let dir = (t1 - t2).normalize();
-        camera_transform.translation += CAMERA_SPEED * dir.extend(0.0);
-    } // else outside window
-}
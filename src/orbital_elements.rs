@@ -0,0 +1,403 @@
+use bevy::prelude::*;
+use std::f32::consts::PI;
+
+use crate::celestial_body::{CelestialBody, TwoBodyProblem};
+use crate::gravity::GravityConfig;
+use crate::prediction::{Conic, ConicKind};
+use crate::selection::Selected;
+use crate::units::SimulationUnits;
+
+pub struct OrbitalElementsPlugin {
+    /// Skips the UI panel, for apps without a window.
+    pub headless: bool,
+}
+
+impl Plugin for OrbitalElementsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, update_orbital_elements);
+
+        if self.headless {
+            return;
+        }
+        app.add_systems(Startup, setup_elements_panel).add_systems(
+            Update,
+            (update_elements_panel, draw_apsides).after(update_orbital_elements),
+        );
+    }
+}
+
+/// Highest eccentricity accepted by [`OrbitalElements::from_periapsis`].
+const MAX_ECCENTRICITY: f32 = 0.95;
+
+/// The Keplerian orbit of a body around its `TwoBodyProblem` primary.
+///
+/// Lengths are in whatever unit the state was given in, the scenario's length unit when placing
+/// bodies and pixels on the component that follows each body. Angles are in radians and the period
+/// is in the scenario's time unit.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct OrbitalElements {
+    /// Negative for hyperbolas and infinite for parabolas.
+    pub semi_major_axis: f32,
+    pub eccentricity: f32,
+    /// Angle of periapsis from the x axis.
+    pub argument_of_periapsis: f32,
+    /// Infinite for open orbits.
+    pub period: f32,
+    /// Angle from periapsis to the body, measured in the direction of motion.
+    pub true_anomaly: f32,
+    /// Whether the body orbits clockwise.
+    pub retrograde: bool,
+}
+
+impl OrbitalElements {
+    /// Solves for the elements from the position and velocity relative to the primary.
+    ///
+    /// `mu` is the gravitational parameter `G (m1 + m2)`. Returns `None` for radial trajectories.
+    pub fn from_state(position: Vec2, velocity: Vec2, mu: f32) -> Option<Self> {
+        let conic = Conic::from_state(position, velocity, mu)?;
+        let retrograde = conic.angular_momentum < 0.0;
+        let semi_major_axis = conic.semi_major_axis();
+        let period = match conic.kind() {
+            ConicKind::Ellipse => 2.0 * PI * (semi_major_axis.powi(3) / mu).sqrt(),
+            ConicKind::Parabola | ConicKind::Hyperbola => f32::INFINITY,
+        };
+
+        let argument_of_periapsis = conic.argument_of_periapsis();
+        let mut true_anomaly = position.to_angle() - argument_of_periapsis;
+        if retrograde {
+            true_anomaly = -true_anomaly;
+        }
+
+        Some(Self {
+            semi_major_axis,
+            eccentricity: conic.eccentricity.length(),
+            argument_of_periapsis,
+            period,
+            true_anomaly: wrap_angle(true_anomaly),
+            retrograde,
+        })
+    }
+
+    /// A closed orbit that passes through `periapsis`, relative to the primary.
+    ///
+    /// The eccentricity is clamped below one. Zero gives a circular orbit.
+    pub fn from_periapsis(periapsis: Vec2, eccentricity: f32, retrograde: bool, mu: f32) -> Self {
+        let eccentricity = eccentricity.clamp(0.0, MAX_ECCENTRICITY);
+        let semi_major_axis = periapsis.length() / (1.0 - eccentricity);
+        Self {
+            semi_major_axis,
+            eccentricity,
+            argument_of_periapsis: periapsis.to_angle(),
+            period: 2.0 * PI * (semi_major_axis.powi(3) / mu).sqrt(),
+            true_anomaly: 0.0,
+            retrograde,
+        }
+    }
+
+    /// A closed orbit given by its mean anomaly, as in published mean elements.
+    pub fn from_mean_anomaly(
+        semi_major_axis: f32,
+        eccentricity: f32,
+        argument_of_periapsis: f32,
+        mean_anomaly: f32,
+        retrograde: bool,
+        mu: f32,
+    ) -> Self {
+        Self {
+            semi_major_axis,
+            eccentricity,
+            argument_of_periapsis,
+            period: 2.0 * PI * (semi_major_axis.powi(3) / mu).sqrt(),
+            true_anomaly: true_anomaly_from_mean(mean_anomaly, eccentricity),
+            retrograde,
+        }
+    }
+
+    /// The position and velocity relative to the primary.
+    ///
+    /// Parabolic orbits have no finite semi-major axis, so they can't be converted back.
+    pub fn to_state(self, mu: f32) -> (Vec2, Vec2) {
+        let semi_latus_rectum = self.semi_major_axis * (1.0 - self.eccentricity.powi(2));
+        let direction = if self.retrograde { -1.0 } else { 1.0 };
+        let (sin, cos) = self.true_anomaly.sin_cos();
+
+        let radial = Vec2::from_angle(self.argument_of_periapsis + direction * self.true_anomaly);
+        let transverse = direction * radial.perp();
+        let radius = semi_latus_rectum / (1.0 + self.eccentricity * cos);
+
+        // Radial and transverse speeds from the vis-viva equation in polar form
+        let speed = (mu / semi_latus_rectum).sqrt();
+        let velocity = speed * self.eccentricity * sin * radial
+            + speed * (1.0 + self.eccentricity * cos) * transverse;
+
+        (radius * radial, velocity)
+    }
+}
+
+/// Solves Kepler's equation `M = E - e sin E` for a closed orbit, returning the true anomaly.
+fn true_anomaly_from_mean(mean_anomaly: f32, eccentricity: f32) -> f32 {
+    let mean_anomaly = wrap_angle(mean_anomaly);
+    // Newton's method, starting from π for high eccentricities where M is a poor guess
+    let mut eccentric_anomaly = if eccentricity > 0.8 {
+        PI.copysign(mean_anomaly)
+    } else {
+        mean_anomaly
+    };
+    for _ in 0..16 {
+        let (sin, cos) = eccentric_anomaly.sin_cos();
+        eccentric_anomaly -=
+            (eccentric_anomaly - eccentricity * sin - mean_anomaly) / (1.0 - eccentricity * cos);
+    }
+
+    let (sin, cos) = (eccentric_anomaly / 2.0).sin_cos();
+    2.0 * ((1.0 + eccentricity).sqrt() * sin).atan2((1.0 - eccentricity).sqrt() * cos)
+}
+
+/// Wraps an angle into (-π, π].
+fn wrap_angle(angle: f32) -> f32 {
+    let wrapped = (angle + PI).rem_euclid(2.0 * PI) - PI;
+    if wrapped == -PI {
+        PI
+    } else {
+        wrapped
+    }
+}
+
+/// Recomputes the orbital elements of every body around its most influential body
+fn update_orbital_elements(
+    mut commands: Commands,
+    units: Res<SimulationUnits>,
+    gravity: Res<GravityConfig>,
+    mut query: Query<(
+        Entity,
+        &CelestialBody,
+        &TwoBodyProblem,
+        Option<&mut OrbitalElements>,
+    )>,
+    bodies: Query<&CelestialBody>,
+) {
+    let pixels_per_length = units.pixels_per_length;
+
+    for (entity, body, two_body_problem, elements) in &mut query {
+        let solved = two_body_problem
+            .entity
+            .and_then(|entity| bodies.get(entity).ok())
+            .and_then(|primary| {
+                let mu =
+                    gravity.gravitational_parameter(body.mass + primary.mass, pixels_per_length)?;
+                OrbitalElements::from_state(
+                    body.position - primary.position,
+                    body.velocity - primary.velocity,
+                    mu,
+                )
+            });
+
+        match (solved, elements) {
+            (Some(solved), Some(mut elements)) => *elements = solved,
+            (Some(solved), None) => {
+                commands.entity(entity).insert(solved);
+            }
+            (None, Some(_)) => {
+                commands.entity(entity).remove::<OrbitalElements>();
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+#[derive(Component)]
+struct ElementsText;
+
+fn setup_elements_panel(mut commands: Commands) {
+    // Sits to the left of the FPS counter
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(5.0),
+                right: Val::Px(260.0),
+                ..Default::default()
+            },
+            background_color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn(TextBundle {
+                    text: Text::from_section(
+                        String::new(),
+                        TextStyle {
+                            font_size: 20.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    ),
+                    ..Default::default()
+                })
+                .insert(ElementsText);
+        });
+}
+
+/// Shows the orbital elements of the selected body
+fn update_elements_panel(
+    units: Res<SimulationUnits>,
+    selected: Query<(&CelestialBody, Option<&OrbitalElements>), With<Selected>>,
+    mut query: Query<&mut Text, With<ElementsText>>,
+) {
+    let value = match selected.get_single() {
+        Ok((body, Some(elements))) => format!(
+            "{}\na: {}\ne: {:.3}\nω: {:.1}°\nT: {}\nν: {:.1}°",
+            body.name,
+            units.format_length(elements.semi_major_axis),
+            elements.eccentricity,
+            elements.argument_of_periapsis.to_degrees(),
+            units.format_time(elements.period),
+            elements.true_anomaly.to_degrees(),
+        ),
+        Ok((body, None)) => format!("{}\nNo orbit", body.name),
+        Err(_) => String::new(),
+    };
+
+    for mut text in &mut query {
+        text.sections[0].value.clone_from(&value);
+    }
+}
+
+/// Marks periapsis and, for closed orbits, apoapsis of the selected body
+fn draw_apsides(
+    mut gizmos: Gizmos,
+    units: Res<SimulationUnits>,
+    gravity: Res<GravityConfig>,
+    selected: Query<(&CelestialBody, &TwoBodyProblem, &OrbitalElements), With<Selected>>,
+    bodies: Query<&CelestialBody>,
+) {
+    let Ok((body, two_body_problem, elements)) = selected.get_single() else {
+        return;
+    };
+    let Some(primary) = two_body_problem
+        .entity
+        .and_then(|entity| bodies.get(entity).ok())
+    else {
+        return;
+    };
+
+    let pixels_per_length = units.pixels_per_length;
+    let Some(mu) = gravity.gravitational_parameter(body.mass + primary.mass, pixels_per_length)
+    else {
+        return;
+    };
+    let color = Color::srgb(1.0, 0.8, 0.2);
+
+    let anomalies: &[f32] = if elements.period.is_finite() {
+        &[0.0, PI]
+    } else {
+        &[0.0]
+    };
+    for &true_anomaly in anomalies {
+        let apsis = OrbitalElements {
+            true_anomaly,
+            ..*elements
+        };
+        let (position, _) = apsis.to_state(mu);
+        gizmos.circle_2d(primary.position + position, 4.0, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MU: f32 = 1000.0;
+
+    fn assert_round_trip(position: Vec2, velocity: Vec2) {
+        let elements = OrbitalElements::from_state(position, velocity, MU).unwrap();
+        let (round_position, round_velocity) = elements.to_state(MU);
+
+        assert!(
+            round_position.distance(position) < 1.0e-3 * position.length(),
+            "{round_position} != {position}"
+        );
+        assert!(
+            round_velocity.distance(velocity) < 1.0e-3 * velocity.length(),
+            "{round_velocity} != {velocity}"
+        );
+    }
+
+    #[test]
+    fn state_round_trips_through_elements() {
+        let position = Vec2::new(8.0, -3.0);
+        assert_round_trip(position, Vec2::new(4.0, 9.0));
+        // Retrograde
+        assert_round_trip(position, Vec2::new(-4.0, -9.0));
+        // Hyperbolic
+        assert_round_trip(position, Vec2::new(10.0, 20.0));
+    }
+
+    #[test]
+    fn circular_orbit_period() {
+        let radius = 10.0;
+        let speed = (MU / radius).sqrt();
+        let elements =
+            OrbitalElements::from_state(Vec2::new(0.0, radius), Vec2::new(speed, 0.0), MU).unwrap();
+
+        assert!(elements.retrograde);
+        assert!((elements.semi_major_axis - radius).abs() < 1.0e-3);
+        assert!((elements.period - 2.0 * PI * radius / speed).abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn orbit_from_periapsis() {
+        let periapsis = Vec2::new(-6.0, 8.0);
+
+        let circular = OrbitalElements::from_periapsis(periapsis, 0.0, false, MU);
+        let (position, velocity) = circular.to_state(MU);
+        assert!(position.distance(periapsis) < 1.0e-4);
+        assert!((velocity.length() - (MU / 10.0).sqrt()).abs() < 1.0e-3);
+        assert!(position.perp_dot(velocity) > 0.0);
+
+        let elliptical = OrbitalElements::from_periapsis(periapsis, 0.6, true, MU);
+        let (position, velocity) = elliptical.to_state(MU);
+        let solved = OrbitalElements::from_state(position, velocity, MU).unwrap();
+        assert!((solved.eccentricity - 0.6).abs() < 1.0e-4);
+        assert!(solved.true_anomaly.abs() < 1.0e-3);
+        assert!(solved.retrograde);
+    }
+
+    #[test]
+    fn true_anomaly_follows_the_motion() {
+        // Half way from periapsis at 0° to apoapsis, for both directions of travel
+        let periapsis = 10.0;
+        let speed = (MU * 1.5 / periapsis).sqrt();
+        for direction in [1.0, -1.0] {
+            let mut elements = OrbitalElements::from_state(
+                Vec2::new(periapsis, 0.0),
+                Vec2::new(0.0, direction * speed),
+                MU,
+            )
+            .unwrap();
+            assert!(elements.true_anomaly.abs() < 1.0e-4);
+
+            elements.true_anomaly = PI / 2.0;
+            let (position, _) = elements.to_state(MU);
+            assert!(position.y * direction > 0.0, "{position}");
+        }
+    }
+
+    #[test]
+    fn true_anomaly_from_mean_anomaly() {
+        // Apsides and circular orbits need no solving
+        assert_eq!(true_anomaly_from_mean(0.0, 0.5), 0.0);
+        assert!((true_anomaly_from_mean(PI, 0.5).abs() - PI).abs() < 1.0e-5);
+        assert!((true_anomaly_from_mean(1.0, 0.0) - 1.0).abs() < 1.0e-5);
+
+        // Kepler's equation holds for the eccentric anomaly of the result
+        for eccentricity in [0.1, 0.5, 0.9] {
+            let true_anomaly = true_anomaly_from_mean(1.0, eccentricity);
+            let eccentric_anomaly = 2.0
+                * ((1.0 - eccentricity).sqrt() * (true_anomaly / 2.0).sin())
+                    .atan2((1.0 + eccentricity).sqrt() * (true_anomaly / 2.0).cos());
+            let mean_anomaly = eccentric_anomaly - eccentricity * eccentric_anomaly.sin();
+            assert!((mean_anomaly - 1.0).abs() < 1.0e-5, "{mean_anomaly}");
+        }
+    }
+}
@@ -0,0 +1,524 @@
+use bevy::prelude::*;
+use std::f32::consts::PI;
+
+use crate::celestial_body::{pin, CelestialBody, Pinned, TwoBodyProblem};
+use crate::clock::SimulationClock;
+use crate::gravity::{GravityConfig, GravitySolver};
+use crate::integrator::{AdaptiveTimestep, Integrator, Pull};
+use crate::selection::Selected;
+use crate::trails::Trail;
+use crate::units::SimulationUnits;
+
+pub struct PredictionPlugin;
+impl Plugin for PredictionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Lookahead>().add_systems(
+            Update,
+            (
+                toggle_prediction_trails,
+                draw_prediction_trails,
+                (toggle_lookahead, update_lookahead, draw_lookahead).chain(),
+            ),
+        );
+    }
+}
+
+/// Draws the Keplerian orbit of a body around its most influential body.
+#[derive(Component, Clone, Debug)]
+pub struct PredictionTrail {
+    pub color: Color,
+    /// Number of line segments used to draw the conic.
+    pub segments: usize,
+}
+
+impl Default for PredictionTrail {
+    fn default() -> Self {
+        Self {
+            color: Color::srgba(0.5, 0.7, 1.0, 0.6),
+            segments: 128,
+        }
+    }
+}
+
+/// Open orbits are drawn out to this multiple of the current separation.
+const OPEN_ORBIT_RANGE: f32 = 4.0;
+
+/// Eccentricities this close to one are treated as parabolic.
+const PARABOLIC_TOLERANCE: f32 = 1.0e-3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConicKind {
+    Ellipse,
+    Parabola,
+    Hyperbola,
+}
+
+/// The conic section traced by one body relative to another in the two body problem.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Conic {
+    /// Points from the primary towards periapsis, with a length of the eccentricity.
+    pub eccentricity: Vec2,
+    /// The radius at a true anomaly of ±90°.
+    pub semi_latus_rectum: f32,
+    /// Specific angular momentum, positive for anticlockwise orbits.
+    pub angular_momentum: f32,
+}
+
+impl Conic {
+    /// Solves for the conic from the relative position and velocity of a body.
+    ///
+    /// `mu` is the gravitational parameter `G (m1 + m2)`. Returns `None` for radial trajectories.
+    pub fn from_state(position: Vec2, velocity: Vec2, mu: f32) -> Option<Self> {
+        let angular_momentum = position.perp_dot(velocity);
+        let radius = position.length();
+        if mu <= 0.0 || radius == 0.0 || angular_momentum.abs() <= f32::EPSILON * radius {
+            return None;
+        }
+
+        let eccentricity = ((velocity.length_squared() - mu / radius) * position
+            - position.dot(velocity) * velocity)
+            / mu;
+
+        Some(Self {
+            eccentricity,
+            semi_latus_rectum: angular_momentum * angular_momentum / mu,
+            angular_momentum,
+        })
+    }
+
+    pub fn kind(&self) -> ConicKind {
+        let eccentricity = self.eccentricity.length();
+        if (eccentricity - 1.0).abs() < PARABOLIC_TOLERANCE {
+            ConicKind::Parabola
+        } else if eccentricity < 1.0 {
+            ConicKind::Ellipse
+        } else {
+            ConicKind::Hyperbola
+        }
+    }
+
+    /// Negative for hyperbolas and infinite for parabolas.
+    pub fn semi_major_axis(&self) -> f32 {
+        self.semi_latus_rectum / (1.0 - self.eccentricity.length_squared())
+    }
+
+    /// Angle of periapsis from the x axis.
+    pub fn argument_of_periapsis(&self) -> f32 {
+        self.eccentricity.y.atan2(self.eccentricity.x)
+    }
+
+    /// Distance from the primary at a given true anomaly.
+    pub fn radius_at(&self, true_anomaly: f32) -> f32 {
+        self.semi_latus_rectum / (1.0 + self.eccentricity.length() * true_anomaly.cos())
+    }
+
+    /// Position relative to the primary at a given true anomaly.
+    pub fn position_at(&self, true_anomaly: f32) -> Vec2 {
+        self.radius_at(true_anomaly) * Vec2::from_angle(self.argument_of_periapsis() + true_anomaly)
+    }
+
+    /// Samples the conic relative to the primary.
+    ///
+    /// Ellipses are closed, open orbits are cut off at `max_radius`.
+    pub fn points(&self, segments: usize, max_radius: f32) -> Vec<Vec2> {
+        let eccentricity = self.eccentricity.length();
+        let limit = match self.kind() {
+            ConicKind::Ellipse => PI,
+            ConicKind::Parabola | ConicKind::Hyperbola => {
+                let cos_limit = (self.semi_latus_rectum / max_radius - 1.0) / eccentricity;
+                if cos_limit > 1.0 {
+                    return Vec::new();
+                }
+                cos_limit.max(-1.0).acos()
+            }
+        };
+
+        (0..=segments)
+            .map(|i| -limit + 2.0 * limit * i as f32 / segments as f32)
+            .map(|true_anomaly| self.position_at(true_anomaly))
+            .collect()
+    }
+}
+
+/// Adds or removes prediction trails on every body when P is pressed
+fn toggle_prediction_trails(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    query: Query<(Entity, Has<PredictionTrail>), With<CelestialBody>>,
+) {
+    if !keys.just_pressed(KeyCode::KeyP) {
+        return;
+    }
+
+    let enable = query.iter().any(|(_, has_prediction)| !has_prediction);
+    for (entity, _) in &query {
+        if enable {
+            commands.entity(entity).insert(PredictionTrail::default());
+        } else {
+            commands.entity(entity).remove::<PredictionTrail>();
+        }
+    }
+}
+
+/// Draws the conic each body would follow if only its most influential body attracted it
+fn draw_prediction_trails(
+    mut gizmos: Gizmos,
+    units: Res<SimulationUnits>,
+    gravity: Res<GravityConfig>,
+    query: Query<(&CelestialBody, &TwoBodyProblem, &PredictionTrail)>,
+    bodies: Query<&CelestialBody>,
+) {
+    let pixels_per_length = units.pixels_per_length;
+
+    for (body, two_body_problem, prediction) in &query {
+        let Some(primary) = two_body_problem
+            .entity
+            .and_then(|entity| bodies.get(entity).ok())
+        else {
+            continue;
+        };
+
+        let Some(mu) = gravity.gravitational_parameter(body.mass + primary.mass, pixels_per_length)
+        else {
+            continue;
+        };
+        let position = body.position - primary.position;
+        let velocity = body.velocity - primary.velocity;
+
+        if let Some(conic) = Conic::from_state(position, velocity, mu) {
+            let points = conic
+                .points(prediction.segments, OPEN_ORBIT_RANGE * position.length())
+                .into_iter()
+                .map(|point| primary.position + point);
+            gizmos.linestrip_2d(points, prediction.color);
+        }
+    }
+}
+
+/// Forward simulates the whole system to predict the path of the selected body.
+///
+/// Collisions are ignored, so the path runs straight through any merge.
+#[derive(Resource, Clone, Debug)]
+pub struct Lookahead {
+    pub enabled: bool,
+    /// How far ahead to simulate, in seconds.
+    pub horizon: f32,
+    /// Recompute once the body is this many pixels away from where it was predicted to be.
+    pub tolerance: f32,
+    cache: Option<LookaheadPath>,
+}
+
+impl Default for Lookahead {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            horizon: 10.0,
+            tolerance: 2.0,
+            cache: None,
+        }
+    }
+}
+
+/// The cached result of a lookahead simulation.
+#[derive(Clone, Debug)]
+struct LookaheadPath {
+    entity: Entity,
+    body_count: usize,
+    /// Simulated time when the snapshot was taken.
+    start: f64,
+    /// Negative when the clock runs backwards.
+    timestep: f32,
+    /// Position of the selected body after each step, starting from the snapshot.
+    path: Vec<Vec2>,
+}
+
+impl LookaheadPath {
+    /// Checks whether the real body still follows the prediction closely enough.
+    fn is_valid(
+        &self,
+        entity: Entity,
+        body_count: usize,
+        now: f64,
+        position: Vec2,
+        tolerance: f32,
+    ) -> bool {
+        // Negative once the clock has changed direction
+        let step = ((now - self.start) / self.timestep as f64).round();
+        self.entity == entity
+            && self.body_count == body_count
+            // Keep at least half the horizon ahead of the body
+            && step >= 0.0
+            && (step as usize) < self.path.len() / 2
+            && self.path[step as usize].distance(position) <= tolerance
+    }
+}
+
+/// Simulates a snapshot of the bodies and returns the path of one of them.
+///
+/// `pull` is given the positions and masses, and should apply the same force law as
+/// `apply_gravity`. Each step is split into substeps by `adaptive` just as the live fixed steps are.
+pub fn simulate_path(
+    bodies: &[CelestialBody],
+    index: usize,
+    steps: usize,
+    timestep: f32,
+    integrator: Integrator,
+    adaptive: &mut AdaptiveTimestep,
+    mut pull: impl FnMut(&[Vec2], &[f32]) -> Pull,
+) -> Vec<Vec2> {
+    let masses: Vec<f32> = bodies.iter().map(|body| body.mass).collect();
+    let mut positions: Vec<Vec2> = bodies.iter().map(|body| body.position).collect();
+    let mut velocities: Vec<Vec2> = bodies.iter().map(|body| body.velocity).collect();
+
+    let mut path = Vec::with_capacity(steps + 1);
+    path.push(positions[index]);
+    for _ in 0..steps {
+        adaptive.step(
+            integrator,
+            &mut positions,
+            &mut velocities,
+            timestep,
+            |positions| pull(positions, &masses),
+        );
+        path.push(positions[index]);
+    }
+    path
+}
+
+/// Turns the lookahead on and off when L is pressed
+fn toggle_lookahead(keys: Res<ButtonInput<KeyCode>>, mut lookahead: ResMut<Lookahead>) {
+    if keys.just_pressed(KeyCode::KeyL) {
+        lookahead.enabled = !lookahead.enabled;
+        lookahead.cache = None;
+    }
+}
+
+/// Re-runs the lookahead when the selected body drifts from its cached path
+#[allow(clippy::too_many_arguments)]
+fn update_lookahead(
+    mut lookahead: ResMut<Lookahead>,
+    time: Res<Time<Fixed>>,
+    clock: Res<SimulationClock>,
+    units: Res<SimulationUnits>,
+    gravity: Res<GravityConfig>,
+    solver: Res<GravitySolver>,
+    integrator: Res<Integrator>,
+    adaptive: Res<AdaptiveTimestep>,
+    query: Query<(Entity, &CelestialBody, Has<Selected>, Has<Pinned>)>,
+) {
+    let Some((selected, body, ..)) = query.iter().find(|(_, _, selected, _)| *selected) else {
+        lookahead.cache = None;
+        return;
+    };
+    if !lookahead.enabled {
+        return;
+    }
+
+    let now = clock.elapsed_seconds();
+    let body_count = query.iter().len();
+    // A new force law invalidates the whole path, not just where the body is now
+    if let Some(cache) = lookahead.cache.as_ref().filter(|_| !gravity.is_changed()) {
+        if cache.is_valid(
+            selected,
+            body_count,
+            now,
+            body.position,
+            lookahead.tolerance,
+        ) {
+            return;
+        }
+    }
+
+    // Snapshot in the same order that `apply_gravity` uses
+    let mut bodies: Vec<(Entity, CelestialBody, bool)> = query
+        .iter()
+        .map(|(entity, body, _, pinned)| (entity, body.clone(), pinned))
+        .collect();
+    bodies.sort_by_key(|(entity, ..)| *entity);
+    let index = bodies
+        .iter()
+        .position(|(entity, ..)| *entity == selected)
+        .unwrap();
+    let pinned: Vec<usize> = (0..bodies.len()).filter(|&i| bodies[i].2).collect();
+    let bodies: Vec<CelestialBody> = bodies.into_iter().map(|(_, body, _)| body).collect();
+
+    let pixels_per_length = units.pixels_per_length;
+    // Look ahead in whichever direction the clock is running
+    let timestep = clock.signed(time.timestep().as_secs_f32());
+    let steps = (lookahead.horizon / timestep.abs()).ceil() as usize;
+    let path = simulate_path(
+        &bodies,
+        index,
+        steps,
+        timestep,
+        *integrator,
+        // A copy, so the overlay keeps showing the substeps of the live step
+        &mut adaptive.clone(),
+        |positions, masses| {
+            let (attractions, accelerations) =
+                solver.accelerations(positions, masses, &gravity, pixels_per_length);
+            let partners = attractions
+                .iter()
+                .map(|attraction| attraction.dominant.map(|(other, _)| other))
+                .collect();
+            (pin(accelerations, &pinned), partners)
+        },
+    );
+
+    lookahead.cache = Some(LookaheadPath {
+        entity: selected,
+        body_count,
+        start: now,
+        timestep,
+        path,
+    });
+}
+
+/// Draws the predicted path in the style of the body's trail
+fn draw_lookahead(mut gizmos: Gizmos, lookahead: Res<Lookahead>, trails: Query<&Trail>) {
+    let Some(cache) = lookahead.cache.as_ref().filter(|_| lookahead.enabled) else {
+        return;
+    };
+
+    let color = trails
+        .get(cache.entity)
+        .map_or(Color::WHITE, |trail| trail.color())
+        .with_alpha(0.5);
+    gizmos.linestrip_2d(cache.path.iter().copied(), color);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::headless_app;
+    use crate::scenario::{BodyDescription, Scenario};
+
+    const MU: f32 = 1000.0;
+
+    #[test]
+    fn circular_orbit_has_zero_eccentricity() {
+        let radius = 10.0;
+        let speed = (MU / radius).sqrt();
+        let conic = Conic::from_state(Vec2::new(radius, 0.0), Vec2::new(0.0, speed), MU).unwrap();
+
+        assert_eq!(conic.kind(), ConicKind::Ellipse);
+        assert!(conic.eccentricity.length() < 1.0e-5);
+        for point in conic.points(32, f32::INFINITY) {
+            assert!((point.length() - radius).abs() < 1.0e-3);
+        }
+    }
+
+    #[test]
+    fn ellipse_from_periapsis() {
+        // At periapsis, v² = mu (1 + e) / r_p
+        let periapsis = 10.0;
+        let eccentricity = 0.5;
+        let speed = (MU * (1.0 + eccentricity) / periapsis).sqrt();
+        let conic =
+            Conic::from_state(Vec2::new(0.0, periapsis), Vec2::new(-speed, 0.0), MU).unwrap();
+
+        assert_eq!(conic.kind(), ConicKind::Ellipse);
+        assert!((conic.eccentricity.length() - eccentricity).abs() < 1.0e-4);
+        assert!((conic.argument_of_periapsis() - PI / 2.0).abs() < 1.0e-4);
+        assert!((conic.radius_at(PI) - 30.0).abs() < 1.0e-3);
+        assert!(conic.angular_momentum > 0.0);
+    }
+
+    #[test]
+    fn escape_speed_is_parabolic() {
+        let radius = 10.0;
+        let speed = (2.0 * MU / radius).sqrt();
+        let conic = Conic::from_state(Vec2::new(radius, 0.0), Vec2::new(0.0, -speed), MU).unwrap();
+
+        assert_eq!(conic.kind(), ConicKind::Parabola);
+        assert!(conic.angular_momentum < 0.0);
+
+        let points = conic.points(16, 100.0);
+        assert!((points[0].length() - 100.0).abs() < 1.0e-2);
+        assert!((points[8].length() - radius).abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn hyperbola_is_cut_off_at_the_range() {
+        let radius = 10.0;
+        let speed = 2.0 * (MU / radius).sqrt();
+        let position = Vec2::new(radius, 0.0);
+        let conic = Conic::from_state(position, Vec2::new(0.0, speed), MU).unwrap();
+
+        assert_eq!(conic.kind(), ConicKind::Hyperbola);
+
+        // The current position is periapsis, and every sample lies on the conic
+        let points = conic.points(64, 50.0);
+        assert!(points.iter().all(|point| point.length() <= 50.0 + 1.0e-2));
+        assert!(points.iter().any(|point| point.distance(position) < 1.0e-3));
+    }
+
+    #[test]
+    fn radial_trajectory_has_no_conic() {
+        assert!(Conic::from_state(Vec2::new(10.0, 0.0), Vec2::new(5.0, 0.0), MU).is_none());
+    }
+
+    #[test]
+    fn lookahead_follows_a_close_flyby() {
+        let bodies = [
+            CelestialBody::default().with_mass(10.0),
+            CelestialBody::default()
+                .with_position(Vec2::new(-300.0, 40.0))
+                .with_velocity(Vec2::new(600.0, 0.0)),
+        ];
+        let mut app = headless_app(Scenario {
+            bodies: bodies
+                .iter()
+                .map(|body| BodyDescription::new(body, &default()))
+                .collect(),
+            ..default()
+        });
+        app.update();
+
+        // Snapshot the spawned bodies in the order `apply_gravity` uses
+        let world = app.world_mut();
+        let mut spawned: Vec<(Entity, CelestialBody)> = world
+            .query::<(Entity, &CelestialBody)>()
+            .iter(world)
+            .map(|(entity, body)| (entity, body.clone()))
+            .collect();
+        spawned.sort_by_key(|(entity, _)| *entity);
+        let (probe, _) = spawned[1];
+        let snapshot: Vec<CelestialBody> = spawned.into_iter().map(|(_, body)| body).collect();
+
+        let steps = 64;
+        let gravity = *world.resource::<GravityConfig>();
+        let solver = *world.resource::<GravitySolver>();
+        let pixels_per_length = world.resource::<SimulationUnits>().pixels_per_length;
+        let path = simulate_path(
+            &snapshot,
+            1,
+            steps,
+            world.resource::<Time<Fixed>>().timestep().as_secs_f32(),
+            *world.resource::<Integrator>(),
+            &mut world.resource::<AdaptiveTimestep>().clone(),
+            |positions, masses| {
+                let (attractions, accelerations) =
+                    solver.accelerations(positions, masses, &gravity, pixels_per_length);
+                let partners = attractions
+                    .iter()
+                    .map(|attraction| attraction.dominant.map(|(other, _)| other))
+                    .collect();
+                (accelerations, partners)
+            },
+        );
+
+        let mut max_substeps = 0;
+        for (step, predicted) in path.iter().enumerate().skip(1) {
+            app.update();
+            let world = app.world();
+            max_substeps = max_substeps.max(world.resource::<AdaptiveTimestep>().last_substeps());
+            let position = world.get::<CelestialBody>(probe).unwrap().position;
+            assert!(
+                predicted.distance(position) < 1.0e-3,
+                "step {step}: predicted {predicted} but the body is at {position}"
+            );
+        }
+        assert!(max_substeps > 1, "the flyby should need substeps");
+    }
+}
@@ -0,0 +1,651 @@
+use bevy::asset::io::{file::FileAssetReader, Reader};
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext, LoadState};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+
+use crate::celestial_body::{
+    CelestialBody, CelestialBodyAssets, CelestialBodyBundle, CelestialBodyKind,
+};
+use crate::collision::CollisionPolicy;
+use crate::generator::Generator;
+use crate::gravity::{ForceLaw, GravityConfig};
+use crate::trails::Trail;
+use crate::units::SimulationUnits;
+
+pub struct ScenarioPlugin;
+impl Plugin for ScenarioPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Scenario>()
+            .init_resource::<ZoomPresets>()
+            .register_asset_loader(ScenarioLoader)
+            .add_systems(Update, (spawn_scenario, save_scenario));
+    }
+}
+
+/// A whole system of bodies, along with the settings needed to reproduce it.
+#[derive(Asset, TypePath, Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Scenario {
+    #[serde(default)]
+    pub settings: ScenarioSettings,
+    #[serde(default)]
+    pub bodies: Vec<BodyDescription>,
+    /// Adds randomly placed bodies to the ones listed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generator: Option<Generator>,
+}
+
+/// Global parameters of the simulation.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "SettingsFile")]
+pub struct ScenarioSettings {
+    /// Defaults to the real constant in the scenario's units when they are given.
+    pub gravitational_constant: f32,
+    /// Plummer softening length in length units, used by the softened force law.
+    pub softening_length: f32,
+    pub force_law: ForceLaw,
+    /// The units body positions, velocities and masses are given in.
+    pub units: SimulationUnits,
+    /// Fixed timestep in time units.
+    pub timestep: f32,
+    /// What happens to bodies that touch, unless they override it.
+    pub collision: CollisionPolicy,
+    /// How far the number keys zoom out, as the distance from the centre to the top of the screen
+    /// in length units. The first one is used on startup.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub zoom_presets: Vec<f32>,
+}
+
+impl Default for ScenarioSettings {
+    fn default() -> Self {
+        Self {
+            gravitational_constant: GravityConfig::default().gravitational_constant,
+            softening_length: GravityConfig::default().softening_length,
+            force_law: ForceLaw::default(),
+            units: SimulationUnits::default(),
+            timestep: 1.0 / 64.0,
+            collision: CollisionPolicy::default(),
+            zoom_presets: Vec::new(),
+        }
+    }
+}
+
+/// [`ScenarioSettings`] as written in a scenario file, where the gravitational constant may follow
+/// from the units, checked before anything is spawned from it.
+#[derive(Deserialize)]
+#[serde(default)]
+struct SettingsFile {
+    #[serde(deserialize_with = "present")]
+    gravitational_constant: Option<f32>,
+    softening_length: f32,
+    force_law: ForceLaw,
+    #[serde(deserialize_with = "present")]
+    units: Option<SimulationUnits>,
+    timestep: f32,
+    collision: CollisionPolicy,
+    zoom_presets: Vec<f32>,
+}
+
+/// Reads a field that is `None` only when left out, so it's written without `Some(..)`.
+fn present<'de, D: serde::Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+impl Default for SettingsFile {
+    fn default() -> Self {
+        let settings = ScenarioSettings::default();
+        Self {
+            gravitational_constant: None,
+            softening_length: settings.softening_length,
+            force_law: settings.force_law,
+            units: None,
+            timestep: settings.timestep,
+            collision: settings.collision,
+            zoom_presets: settings.zoom_presets,
+        }
+    }
+}
+
+impl TryFrom<SettingsFile> for ScenarioSettings {
+    type Error = String;
+
+    fn try_from(file: SettingsFile) -> Result<Self, Self::Error> {
+        // A scenario authored in real units would otherwise need G worked out by hand
+        let gravitational_constant = match (file.gravitational_constant, &file.units) {
+            (Some(gravitational_constant), _) => gravitational_constant,
+            (None, Some(units)) => units.gravitational_constant() as f32,
+            (None, None) => GravityConfig::default().gravitational_constant,
+        };
+
+        let units = file.units.unwrap_or_default();
+        if !(1.0..).contains(&units.pixels_per_length) {
+            return Err(format!(
+                "pixels_per_length must be at least 1.0, not {}",
+                units.pixels_per_length
+            ));
+        }
+        Ok(Self {
+            gravitational_constant,
+            softening_length: file.softening_length,
+            force_law: file.force_law,
+            units,
+            timestep: file.timestep,
+            collision: file.collision,
+            zoom_presets: file.zoom_presets,
+        })
+    }
+}
+
+/// The initial conditions of a single body, in the scenario's units.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BodyDescription {
+    #[serde(default)]
+    pub name: String,
+    pub position: Vec2,
+    #[serde(default)]
+    pub velocity: Vec2,
+    pub mass: f32,
+    /// Radius in length units, instead of the one that follows from the mass.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub radius: Option<f32>,
+    /// Angular velocity in radians per time unit.
+    #[serde(default)]
+    pub spin: f32,
+    #[serde(default)]
+    pub trail: TrailSettings,
+    /// Kind to use instead of the one that follows from the mass, which picks the sprite.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<CelestialBodyKind>,
+    /// Image to use instead of the one picked from the body's kind.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sprite: Option<String>,
+    /// Overrides the scenario's collision policy for this body.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collision: Option<CollisionPolicy>,
+}
+
+impl Default for BodyDescription {
+    fn default() -> Self {
+        Self::new(&CelestialBody::default(), &SimulationUnits::default())
+    }
+}
+
+impl BodyDescription {
+    pub fn new(body: &CelestialBody, units: &SimulationUnits) -> Self {
+        Self {
+            name: body.name.clone(),
+            position: units.pixels_to_units(body.position),
+            velocity: units.pixels_to_units(body.velocity),
+            mass: body.mass,
+            radius: (body.density != CelestialBody::default_density())
+                .then(|| body.radius() / units.pixels_per_length),
+            spin: body.spin,
+            trail: TrailSettings::default(),
+            kind: (body.kind != CelestialBodyKind::from_mass(body.mass)).then_some(body.kind),
+            sprite: None,
+            collision: None,
+        }
+    }
+
+    pub fn body(&self, units: &SimulationUnits) -> CelestialBody {
+        let density = match self.radius {
+            Some(radius) => self.mass / (PI * (radius * units.pixels_per_length).powi(2)),
+            None => CelestialBody::default_density(),
+        };
+        CelestialBody {
+            name: self.name.clone(),
+            density,
+            kind: self
+                .kind
+                .unwrap_or_else(|| CelestialBodyKind::from_mass(self.mass)),
+            ..CelestialBody::default()
+                .with_mass(self.mass)
+                .with_position(units.units_to_pixels(self.position))
+                .with_velocity(units.units_to_pixels(self.velocity))
+                .with_spin(self.spin)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct TrailSettings {
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<Srgba>,
+}
+
+impl Default for TrailSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            color: None,
+        }
+    }
+}
+
+/// The formats a scenario can be stored in, picked from the file extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScenarioFormat {
+    Ron,
+    Json,
+}
+
+impl ScenarioFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Self::Json,
+            _ => Self::Ron,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+    RonSerialize(ron::Error),
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "could not read scenario: {error}"),
+            Self::Ron(error) => write!(f, "invalid RON scenario: {error}"),
+            Self::RonSerialize(error) => write!(f, "could not write RON scenario: {error}"),
+            Self::Json(error) => write!(f, "invalid JSON scenario: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<std::io::Error> for ScenarioError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl Scenario {
+    pub fn from_bytes(bytes: &[u8], format: ScenarioFormat) -> Result<Self, ScenarioError> {
+        match format {
+            ScenarioFormat::Ron => ron::de::from_bytes(bytes).map_err(ScenarioError::Ron),
+            ScenarioFormat::Json => serde_json::from_slice(bytes).map_err(ScenarioError::Json),
+        }
+    }
+
+    /// Reads a scenario file from a path relative to the assets folder, as the asset server would.
+    pub fn read(path: &Path) -> Result<Self, ScenarioError> {
+        let bytes = std::fs::read(asset_path(path))?;
+        Self::from_bytes(&bytes, ScenarioFormat::from_path(path))
+    }
+
+    pub fn to_string(&self, format: ScenarioFormat) -> Result<String, ScenarioError> {
+        match format {
+            ScenarioFormat::Ron => {
+                ron::ser::to_string_pretty(self, default()).map_err(ScenarioError::RonSerialize)
+            }
+            ScenarioFormat::Json => serde_json::to_string_pretty(self).map_err(ScenarioError::Json),
+        }
+    }
+}
+
+/// Resolves a path against the assets folder, so scenarios are saved where `--scenario` loads them.
+///
+/// Absolute paths are kept as they are.
+pub fn asset_path(path: &Path) -> PathBuf {
+    FileAssetReader::new(AssetPlugin::default().file_path)
+        .root_path()
+        .join(path)
+}
+
+#[derive(Default)]
+struct ScenarioLoader;
+
+impl AssetLoader for ScenarioLoader {
+    type Asset = Scenario;
+    type Settings = ();
+    type Error = ScenarioError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _: &'a Self::Settings,
+        load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Scenario::from_bytes(&bytes, ScenarioFormat::from_path(load_context.path()))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["scenario.ron", "scenario.json"]
+    }
+}
+
+/// The scenario to spawn on startup and where to save the live world.
+#[derive(Resource)]
+pub struct ActiveScenario {
+    pub handle: Handle<Scenario>,
+    /// Relative to the assets folder, see [`asset_path`].
+    pub save_path: PathBuf,
+    spawned: bool,
+}
+
+impl ActiveScenario {
+    pub fn new(handle: Handle<Scenario>, save_path: PathBuf) -> Self {
+        Self {
+            handle,
+            save_path,
+            spawned: false,
+        }
+    }
+}
+
+/// The camera zoom levels of the active scenario, see [`ScenarioSettings::zoom_presets`].
+#[derive(Resource, Clone, Debug, Default)]
+pub struct ZoomPresets(pub Vec<f32>);
+
+/// Marks a body whose sprite was overridden by the scenario, so it can be saved again.
+#[derive(Component, Clone, Debug)]
+pub struct SpriteOverride(pub String);
+
+/// The resources configured by [`ScenarioSettings`].
+#[derive(SystemParam)]
+struct Settings<'w> {
+    gravity: ResMut<'w, GravityConfig>,
+    units: ResMut<'w, SimulationUnits>,
+    rapier_config: ResMut<'w, RapierConfiguration>,
+    time: ResMut<'w, Time<Fixed>>,
+    collision: ResMut<'w, CollisionPolicy>,
+    zoom_presets: ResMut<'w, ZoomPresets>,
+}
+
+impl Settings<'_> {
+    fn apply(&mut self, settings: &ScenarioSettings) {
+        *self.gravity = GravityConfig {
+            gravitational_constant: settings.gravitational_constant,
+            softening_length: settings.softening_length,
+            force_law: settings.force_law,
+        };
+        self.units.clone_from(&settings.units);
+        self.time.set_timestep_seconds(settings.timestep as f64);
+        self.rapier_config.timestep_mode = TimestepMode::Fixed {
+            dt: settings.timestep,
+            substeps: 1,
+        };
+        *self.collision = settings.collision;
+        self.zoom_presets.0.clone_from(&settings.zoom_presets);
+    }
+
+    fn current(&self) -> ScenarioSettings {
+        ScenarioSettings {
+            gravitational_constant: self.gravity.gravitational_constant,
+            softening_length: self.gravity.softening_length,
+            force_law: self.gravity.force_law,
+            units: self.units.clone(),
+            timestep: self.time.timestep().as_secs_f32(),
+            collision: *self.collision,
+            zoom_presets: self.zoom_presets.0.clone(),
+        }
+    }
+}
+
+/// Applies the settings and spawns the bodies once the active scenario has loaded.
+fn spawn_scenario(
+    mut commands: Commands,
+    mut active: ResMut<ActiveScenario>,
+    scenarios: Res<Assets<Scenario>>,
+    asset_server: Res<AssetServer>,
+    celestial_body_assets: Res<CelestialBodyAssets>,
+    mut settings: Settings,
+) {
+    if active.spawned {
+        return;
+    }
+
+    let Some(scenario) = scenarios.get(&active.handle) else {
+        if let Some(LoadState::Failed(error)) = asset_server.get_load_state(&active.handle) {
+            error!("Failed to load scenario: {error}");
+            active.spawned = true;
+        }
+        return;
+    };
+    active.spawned = true;
+
+    settings.apply(&scenario.settings);
+
+    let generated = scenario
+        .generator
+        .as_ref()
+        .map(|generator| generator.generate(scenario.settings.gravitational_constant))
+        .unwrap_or_default();
+    for description in scenario.bodies.iter().chain(&generated) {
+        let mut bundle =
+            CelestialBodyBundle::new(description.body(&settings.units), &celestial_body_assets);
+        if let Some(sprite) = &description.sprite {
+            bundle.sprite.texture = celestial_body_assets.load_sprite(sprite);
+        }
+
+        let mut entity = commands.spawn(bundle);
+        if let Some(sprite) = &description.sprite {
+            entity.insert(SpriteOverride(sprite.clone()));
+        }
+        if let Some(policy) = description.collision {
+            entity.insert(policy);
+        }
+        if description.trail.enabled {
+            let trail = match description.trail.color {
+                Some(color) => Trail::default().with_color(color.into()),
+                None => Trail::default(),
+            };
+            entity.insert(trail);
+        }
+    }
+}
+
+/// Everything about a body that is written to a scenario.
+type SavedBody<'a> = (
+    &'a CelestialBody,
+    Option<&'a Trail>,
+    Option<&'a SpriteOverride>,
+    Option<&'a CollisionPolicy>,
+);
+
+/// Writes the live world to the save path when F5 is pressed.
+fn save_scenario(
+    keys: Res<ButtonInput<KeyCode>>,
+    active: Res<ActiveScenario>,
+    settings: Settings,
+    query: Query<SavedBody>,
+) {
+    if !keys.just_pressed(KeyCode::F5) {
+        return;
+    }
+
+    let scenario = Scenario {
+        settings: settings.current(),
+        bodies: query
+            .iter()
+            .map(|(body, trail, sprite, collision)| BodyDescription {
+                trail: TrailSettings {
+                    enabled: trail.is_some(),
+                    color: trail.map(|trail| trail.color().into()),
+                },
+                sprite: sprite.map(|sprite| sprite.0.clone()),
+                collision: collision.copied(),
+                ..BodyDescription::new(body, &settings.units)
+            })
+            .collect(),
+        // The generated bodies are already in the list
+        generator: None,
+    };
+
+    let path = &asset_path(&active.save_path);
+    let result = scenario
+        .to_string(ScenarioFormat::from_path(path))
+        .and_then(|contents| Ok(std::fs::write(path, contents)?));
+    match result {
+        Ok(()) => info!("Saved scenario to {}", path.display()),
+        Err(error) => error!("Failed to save scenario to {}: {error}", path.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::headless_app;
+
+    #[test]
+    fn round_trips_through_both_formats() {
+        let scenario = Scenario {
+            settings: ScenarioSettings::default(),
+            bodies: vec![BodyDescription {
+                name: "Sun".to_string(),
+                position: Vec2::new(1.0, -2.0),
+                velocity: Vec2::new(0.5, 0.25),
+                mass: 10.0,
+                radius: Some(0.5),
+                spin: 0.25,
+                trail: TrailSettings {
+                    enabled: false,
+                    color: Some(Srgba::RED),
+                },
+                kind: Some(CelestialBodyKind::Star),
+                sprite: Some("sprites/sun.png".to_string()),
+                collision: Some(CollisionPolicy::Bounce { restitution: 0.5 }),
+            }],
+            generator: Some(Generator::galaxies().with_seed(7)),
+        };
+
+        for format in [ScenarioFormat::Ron, ScenarioFormat::Json] {
+            let contents = scenario.to_string(format).unwrap();
+            assert_eq!(
+                Scenario::from_bytes(contents.as_bytes(), format).unwrap(),
+                scenario
+            );
+        }
+    }
+
+    #[test]
+    fn bodies_default_to_a_trail_and_no_velocity() {
+        let scenario = Scenario::from_bytes(
+            b"(bodies: [(position: (1.0, 2.0), mass: 3.0)])",
+            ScenarioFormat::Ron,
+        )
+        .unwrap();
+
+        assert_eq!(scenario.settings, ScenarioSettings::default());
+        assert_eq!(scenario.bodies[0].velocity, Vec2::ZERO);
+        assert!(scenario.bodies[0].trail.enabled);
+    }
+
+    #[test]
+    fn saves_where_it_can_be_loaded_again() {
+        let bodies = [
+            CelestialBody::default().with_mass(10.0),
+            CelestialBody::default()
+                .with_position(Vec2::new(150.0, 0.0))
+                .with_velocity(Vec2::new(0.0, 90.0)),
+        ];
+        let mut app = headless_app(Scenario {
+            bodies: bodies
+                .iter()
+                .map(|body| BodyDescription::new(body, &default()))
+                .collect(),
+            ..default()
+        });
+        app.update();
+
+        // Relative, like the default save path
+        let path = PathBuf::from(format!("saved_{}.scenario.ron", std::process::id()));
+        app.world_mut().resource_mut::<ActiveScenario>().save_path = path.clone();
+        app.world_mut()
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .press(KeyCode::F5);
+        app.update();
+
+        // Read back from where `--scenario` looks
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        assert!(assets.join(&path).exists());
+        let saved = Scenario::read(&path).unwrap();
+        std::fs::remove_file(asset_path(&path)).unwrap();
+
+        let world = app.world_mut();
+        let live: Vec<CelestialBody> = world
+            .query::<&CelestialBody>()
+            .iter(world)
+            .cloned()
+            .collect();
+        assert_eq!(saved.bodies.len(), live.len());
+        for (description, body) in saved.bodies.iter().zip(&live) {
+            let loaded = description.body(&saved.settings.units);
+            assert!(loaded.position.distance(body.position) < 1.0e-3);
+            assert!(loaded.velocity.distance(body.velocity) < 1.0e-3);
+            assert_eq!(loaded.mass, body.mass);
+        }
+    }
+
+    #[test]
+    fn rejects_units_smaller_than_a_pixel() {
+        for pixels_per_length in ["0.5", "-1.0", "NaN"] {
+            let ron = format!("(settings: (units: (pixels_per_length: {pixels_per_length})))");
+            let error = Scenario::from_bytes(ron.as_bytes(), ScenarioFormat::Ron).unwrap_err();
+            assert!(error.to_string().contains("pixels_per_length"), "{error}");
+        }
+    }
+
+    #[test]
+    fn gravitational_constant_defaults_to_the_units() {
+        let scenario = Scenario::from_bytes(
+            br#"(settings: (units: (
+                pixels_per_length: 200.0,
+                length: (symbol: "AU", si: 1.495978707e11),
+                mass: (symbol: "M", si: 1.98847e30),
+                time: (symbol: "d", si: 86400.0),
+            )))"#,
+            ScenarioFormat::Ron,
+        )
+        .unwrap();
+        // The Gaussian gravitational constant squared
+        let g = scenario.settings.gravitational_constant;
+        assert!((g - 2.959e-4).abs() < 1.0e-6, "{g}");
+
+        // A constant that is given wins, and without units the default stays
+        let scenario = Scenario::from_bytes(
+            b"(settings: (gravitational_constant: 2.0, units: (pixels_per_length: 10.0)))",
+            ScenarioFormat::Ron,
+        )
+        .unwrap();
+        assert_eq!(scenario.settings.gravitational_constant, 2.0);
+        let scenario =
+            Scenario::from_bytes(b"(settings: (timestep: 0.5))", ScenarioFormat::Ron).unwrap();
+        assert_eq!(
+            scenario.settings.gravitational_constant,
+            ScenarioSettings::default().gravitational_constant
+        );
+    }
+
+    #[test]
+    fn bundled_scenarios_parse() {
+        let scenario = Scenario::from_bytes(
+            include_bytes!("../assets/scenarios/three_body.scenario.ron"),
+            ScenarioFormat::Ron,
+        )
+        .unwrap();
+        assert_eq!(scenario.bodies.len(), 3);
+
+        let scenario = Scenario::from_bytes(
+            include_bytes!("../assets/scenarios/protoplanetary_disk.scenario.ron"),
+            ScenarioFormat::Ron,
+        )
+        .unwrap();
+        assert_eq!(scenario.generator.unwrap().count, 150);
+    }
+}
@@ -0,0 +1,590 @@
+use bevy::ecs::system::SystemParam;
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::input::{ButtonState, InputSystem};
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::celestial_body::{CelestialBody, CelestialBodyAssets, Pinned};
+use crate::trails::Trail;
+use crate::units::SimulationUnits;
+use crate::{MainCamera, WorldCursor};
+
+pub struct SelectionPlugin;
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Inspector>()
+            .add_systems(Startup, setup_inspector)
+            // Typing has to swallow the keys before any shortcut sees them
+            .add_systems(PreUpdate, type_into_inspector.after(InputSystem))
+            .add_systems(
+                Update,
+                (
+                    (cycle_selection, select_on_click, clear_selection),
+                    (inspector_fields, inspector_actions),
+                    (update_inspector, draw_selection_highlight),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Marks the body the user is currently inspecting.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Selected;
+
+/// How far from a body a click still picks it, in pixels on screen.
+const PICK_TOLERANCE: f32 = 4.0;
+
+/// Finds bodies under the cursor through Rapier's query pipeline.
+#[derive(SystemParam)]
+pub struct BodyPicker<'w, 's> {
+    rapier_context: Res<'w, RapierContext>,
+    camera: Query<'w, 's, &'static OrthographicProjection, With<MainCamera>>,
+    bodies: Query<'w, 's, &'static CelestialBody>,
+}
+
+impl BodyPicker<'_, '_> {
+    /// The body whose edge is closest to `point`, within `PICK_TOLERANCE` on screen.
+    pub fn body_at(&self, point: Vec2) -> Option<Entity> {
+        let scale = self
+            .camera
+            .get_single()
+            .map_or(1.0, |projection| projection.scale);
+        let mut closest: Option<(Entity, f32)> = None;
+        self.rapier_context.intersections_with_shape(
+            point,
+            0.0,
+            &Collider::ball(PICK_TOLERANCE * scale),
+            QueryFilter::default(),
+            |entity| {
+                if let Ok(body) = self.bodies.get(entity) {
+                    let gap = body.position.distance(point) - body.radius();
+                    if closest.is_none_or(|(_, closest_gap)| gap < closest_gap) {
+                        closest = Some((entity, gap));
+                    }
+                }
+                true
+            },
+        );
+        closest.map(|(entity, _)| entity)
+    }
+}
+
+/// Moves the `Selected` marker to `entity`.
+fn select(
+    commands: &mut Commands,
+    selected: impl IntoIterator<Item = Entity>,
+    entity: Option<Entity>,
+) {
+    for previous in selected {
+        commands.entity(previous).remove::<Selected>();
+    }
+    if let Some(entity) = entity {
+        commands.entity(entity).insert(Selected);
+    }
+}
+
+/// Moves the selection to the next body when Tab is pressed
+fn cycle_selection(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    query: Query<(Entity, Has<Selected>), With<CelestialBody>>,
+) {
+    if !keys.just_pressed(KeyCode::Tab) {
+        return;
+    }
+
+    let mut bodies: Vec<(Entity, bool)> = query.iter().collect();
+    bodies.sort_by_key(|(entity, _)| *entity);
+
+    let current = bodies.iter().position(|(_, selected)| *selected);
+    let next = current.map_or(0, |index| index + 1);
+
+    let selected = bodies
+        .iter()
+        .filter(|(_, selected)| *selected)
+        .map(|(entity, _)| *entity);
+    select(
+        &mut commands,
+        selected,
+        bodies.get(next).map(|(entity, _)| *entity),
+    );
+}
+
+/// Selects the body under the cursor when it is clicked
+fn select_on_click(
+    mut commands: Commands,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    cursor: WorldCursor,
+    picker: BodyPicker,
+    selected: Query<Entity, With<Selected>>,
+) {
+    if !mouse_button_input.just_pressed(MouseButton::Left) || cursor.over_ui() {
+        return;
+    }
+    if let Some(entity) = cursor.position().and_then(|point| picker.body_at(point)) {
+        select(&mut commands, &selected, Some(entity));
+    }
+}
+
+/// Drops the selection when Escape is pressed
+fn clear_selection(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    selected: Query<Entity, With<Selected>>,
+) {
+    if keys.just_pressed(KeyCode::Escape) {
+        select(&mut commands, &selected, None);
+    }
+}
+
+/// Circles the selected body, at least a few pixels out from its sprite
+fn draw_selection_highlight(
+    mut gizmos: Gizmos,
+    camera: Query<&OrthographicProjection, With<MainCamera>>,
+    selected: Query<(&CelestialBody, Has<Pinned>), With<Selected>>,
+) {
+    let Ok((body, pinned)) = selected.get_single() else {
+        return;
+    };
+    let scale = camera
+        .get_single()
+        .map_or(1.0, |projection| projection.scale);
+    let radius = body.radius().max(PICK_TOLERANCE * scale) + PICK_TOLERANCE * scale;
+    let color = if pinned {
+        Color::srgb(1.0, 0.4, 0.4)
+    } else {
+        Color::srgb(1.0, 1.0, 0.4)
+    };
+    gizmos.circle_2d(body.position, radius, color);
+}
+
+/// A property of the selected body that can be edited from the inspector.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+enum InspectorField {
+    Name,
+    Mass,
+    PositionX,
+    PositionY,
+    VelocityX,
+    VelocityY,
+    TrailColor,
+}
+
+/// A new value for one of the inspector's fields.
+#[derive(Debug, PartialEq)]
+enum Edit {
+    Body(CelestialBody),
+    TrailColor(Color),
+}
+
+impl InspectorField {
+    const ALL: [Self; 7] = [
+        Self::Name,
+        Self::Mass,
+        Self::PositionX,
+        Self::PositionY,
+        Self::VelocityX,
+        Self::VelocityY,
+        Self::TrailColor,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Mass => "mass",
+            Self::PositionX => "x",
+            Self::PositionY => "y",
+            Self::VelocityX => "vx",
+            Self::VelocityY => "vy",
+            Self::TrailColor => "trail",
+        }
+    }
+
+    /// The current value, in the scenario's units.
+    fn value(self, body: &CelestialBody, trail: Option<&Trail>, units: &SimulationUnits) -> String {
+        let position = units.pixels_to_units(body.position);
+        let velocity = units.pixels_to_units(body.velocity);
+        match self {
+            Self::Name => body.name.clone(),
+            Self::Mass => format!("{} {}", body.mass, units.mass.symbol),
+            Self::PositionX => format!("{} {}", position.x, units.length.symbol),
+            Self::PositionY => format!("{} {}", position.y, units.length.symbol),
+            Self::VelocityX => velocity.x.to_string(),
+            Self::VelocityY => velocity.y.to_string(),
+            Self::TrailColor => match trail {
+                Some(trail) => Srgba::from(trail.color()).to_hex(),
+                None => "none".to_string(),
+            },
+        }
+    }
+
+    /// Parses typed input in the scenario's units, or `None` if it isn't a valid value.
+    fn parse(self, input: &str, body: &CelestialBody, units: &SimulationUnits) -> Option<Edit> {
+        let input = input.trim();
+        let number = || input.parse::<f32>().ok().filter(|value| value.is_finite());
+        let position = units.pixels_to_units(body.position);
+        let velocity = units.pixels_to_units(body.velocity);
+
+        let body = match self {
+            Self::Name => CelestialBody {
+                name: input.to_string(),
+                ..body.clone()
+            },
+            Self::Mass => body.with_mass(number().filter(|&mass| mass > 0.0)?),
+            Self::PositionX => {
+                body.with_position(units.units_to_pixels(position.with_x(number()?)))
+            }
+            Self::PositionY => {
+                body.with_position(units.units_to_pixels(position.with_y(number()?)))
+            }
+            Self::VelocityX => {
+                body.with_velocity(units.units_to_pixels(velocity.with_x(number()?)))
+            }
+            Self::VelocityY => {
+                body.with_velocity(units.units_to_pixels(velocity.with_y(number()?)))
+            }
+            Self::TrailColor => {
+                return Srgba::hex(input)
+                    .ok()
+                    .map(|color| Edit::TrailColor(color.into()))
+            }
+        };
+        Some(Edit::Body(body))
+    }
+}
+
+/// An action on the selected body from the inspector.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+enum InspectorAction {
+    Pin,
+    Delete,
+}
+
+impl InspectorAction {
+    const ALL: [Self; 2] = [Self::Pin, Self::Delete];
+
+    fn label(self, pinned: bool) -> &'static str {
+        match self {
+            Self::Pin if pinned => "Unpin",
+            Self::Pin => "Pin",
+            Self::Delete => "Delete",
+        }
+    }
+}
+
+/// The field being typed into, if any.
+#[derive(Resource, Default)]
+struct Inspector {
+    editing: Option<(InspectorField, String)>,
+}
+
+#[derive(Component)]
+struct InspectorPanel;
+
+const BUTTON_COLOR: Color = Color::srgba(0.2, 0.2, 0.2, 0.8);
+const EDITING_COLOR: Color = Color::srgba(0.3, 0.3, 0.6, 0.8);
+
+fn setup_inspector(mut commands: Commands) {
+    let text_style = TextStyle {
+        font_size: 20.0,
+        color: Color::WHITE,
+        ..default()
+    };
+    let button = || ButtonBundle {
+        style: Style {
+            padding: UiRect::axes(Val::Px(8.0), Val::Px(2.0)),
+            ..Default::default()
+        },
+        background_color: BUTTON_COLOR.into(),
+        ..Default::default()
+    };
+
+    // Sits above the bottom right corner, clear of the clock controls
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(5.0),
+                right: Val::Px(5.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Stretch,
+                row_gap: Val::Px(2.0),
+                ..Default::default()
+            },
+            visibility: Visibility::Hidden,
+            ..Default::default()
+        })
+        .insert(InspectorPanel)
+        .with_children(|parent| {
+            for field in InspectorField::ALL {
+                parent
+                    .spawn(button())
+                    .insert(field)
+                    .with_children(|button| {
+                        button.spawn(TextBundle::from_section(String::new(), text_style.clone()));
+                    });
+            }
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(5.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .with_children(|row| {
+                    for action in InspectorAction::ALL {
+                        row.spawn(button()).insert(action).with_children(|button| {
+                            button.spawn(TextBundle::from_section(
+                                action.label(false),
+                                text_style.clone(),
+                            ));
+                        });
+                    }
+                });
+        });
+}
+
+/// Starts typing into a field when it is clicked
+fn inspector_fields(
+    mut inspector: ResMut<Inspector>,
+    query: Query<(&Interaction, &InspectorField), Changed<Interaction>>,
+) {
+    for (interaction, field) in &query {
+        if *interaction == Interaction::Pressed {
+            inspector.editing = Some((*field, String::new()));
+        }
+    }
+}
+
+/// What the inspector shows besides the body itself.
+type InspectedExtras<'a> = (Option<&'a Trail>, Has<Pinned>);
+
+/// Pins or deletes the selected body
+fn inspector_actions(
+    mut commands: Commands,
+    query: Query<(&Interaction, &InspectorAction), Changed<Interaction>>,
+    mut selected: Query<(Entity, &mut CelestialBody, InspectedExtras), With<Selected>>,
+) {
+    let Ok((entity, mut body, (trail, pinned))) = selected.get_single_mut() else {
+        return;
+    };
+    for (interaction, action) in &query {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match action {
+            InspectorAction::Pin if pinned => {
+                commands.entity(entity).remove::<Pinned>();
+            }
+            InspectorAction::Pin => {
+                // Pinned bodies have no velocity, so they stay exactly where they are
+                body.velocity = Vec2::ZERO;
+                commands.entity(entity).insert(Pinned);
+            }
+            InspectorAction::Delete => {
+                if let Some(trail) = trail {
+                    commands.spawn(trail.with_fadeout(2.0));
+                }
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+}
+
+/// The components a body's properties are copied into.
+type BodyComponents<'a> = (
+    &'a mut CelestialBody,
+    &'a mut Transform,
+    &'a mut Collider,
+    &'a mut Name,
+    &'a mut Handle<Image>,
+    Option<&'a mut Trail>,
+);
+
+/// Takes the keyboard while a field is being typed into, and applies it on Enter
+fn type_into_inspector(
+    mut commands: Commands,
+    mut inspector: ResMut<Inspector>,
+    mut events: EventReader<KeyboardInput>,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    assets: Res<CelestialBodyAssets>,
+    units: Res<SimulationUnits>,
+    mut selected: Query<(Entity, BodyComponents), With<Selected>>,
+) {
+    let Ok((entity, (mut body, mut transform, mut collider, mut name, mut image, trail))) =
+        selected.get_single_mut()
+    else {
+        inspector.editing = None;
+        return;
+    };
+    let Some((field, input)) = &mut inspector.editing else {
+        events.clear();
+        return;
+    };
+    let field = *field;
+    keys.reset_all();
+
+    // Enter applies the input and Escape throws it away
+    let mut finished = None;
+    for event in events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Character(text) => input.push_str(text),
+            Key::Space => input.push(' '),
+            Key::Backspace => {
+                input.pop();
+            }
+            Key::Enter => finished = Some(true),
+            Key::Escape => finished = Some(false),
+            _ => {}
+        }
+        if finished.is_some() {
+            break;
+        }
+    }
+    let Some(apply) = finished else {
+        return;
+    };
+    let input = std::mem::take(input);
+    inspector.editing = None;
+    if !apply {
+        return;
+    }
+
+    match field.parse(&input, &body, &units) {
+        Some(Edit::Body(edited)) => {
+            // Keep the sprite, collider and name in step, as `CelestialBodyBundle` sets them up
+            if edited.kind != body.kind {
+                *image = assets.image(edited.kind);
+            }
+            transform.translation = edited.position.extend(transform.translation.z);
+            *collider = Collider::ball(edited.radius());
+            name.set(edited.name.clone());
+            *body = edited;
+        }
+        Some(Edit::TrailColor(color)) => match trail {
+            Some(mut trail) => *trail = trail.with_color(color),
+            None => {
+                commands
+                    .entity(entity)
+                    .insert(Trail::default().with_color(color));
+            }
+        },
+        None => {}
+    }
+}
+
+/// Shows the selected body's properties, and hides the panel when nothing is selected
+fn update_inspector(
+    inspector: Res<Inspector>,
+    units: Res<SimulationUnits>,
+    selected: Query<(&CelestialBody, InspectedExtras), With<Selected>>,
+    mut panel: Query<&mut Visibility, With<InspectorPanel>>,
+    mut fields: Query<(&InspectorField, &Children, &mut BackgroundColor)>,
+    actions: Query<(&InspectorAction, &Children)>,
+    mut texts: Query<&mut Text>,
+) {
+    let selected = selected.get_single().ok();
+    for mut visibility in &mut panel {
+        *visibility = match selected {
+            Some(_) => Visibility::Inherited,
+            None => Visibility::Hidden,
+        };
+    }
+    let Some((body, (trail, pinned))) = selected else {
+        return;
+    };
+
+    for (field, children, mut background) in &mut fields {
+        let value = match &inspector.editing {
+            Some((editing, input)) if editing == field => {
+                *background = EDITING_COLOR.into();
+                format!("{}: {input}_", field.label())
+            }
+            _ => {
+                *background = BUTTON_COLOR.into();
+                format!("{}: {}", field.label(), field.value(body, trail, &units))
+            }
+        };
+        for &child in children {
+            if let Ok(mut text) = texts.get_mut(child) {
+                text.sections[0].value.clone_from(&value);
+            }
+        }
+    }
+    for (action, children) in &actions {
+        for &child in children {
+            if let Ok(mut text) = texts.get_mut(child) {
+                text.sections[0].value = action.label(pinned).to_string();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body() -> CelestialBody {
+        CelestialBody::default()
+            .with_position(Vec2::new(150.0, -50.0))
+            .with_velocity(Vec2::new(10.0, 20.0))
+    }
+
+    fn edited(field: InspectorField, input: &str) -> Option<CelestialBody> {
+        match field.parse(input, &body(), &SimulationUnits::default())? {
+            Edit::Body(body) => Some(body),
+            Edit::TrailColor(_) => None,
+        }
+    }
+
+    #[test]
+    fn fields_are_edited_in_scenario_units() {
+        let units = SimulationUnits::default();
+        assert_eq!(
+            InspectorField::PositionX.value(&body(), None, &units),
+            "1.5 m"
+        );
+        assert_eq!(
+            edited(InspectorField::PositionY, " 2 ").unwrap().position,
+            Vec2::new(150.0, 200.0)
+        );
+        assert_eq!(
+            edited(InspectorField::VelocityX, "-0.5").unwrap().velocity,
+            Vec2::new(-50.0, 20.0)
+        );
+    }
+
+    #[test]
+    fn mass_edits_resize_the_body() {
+        let heavy = edited(InspectorField::Mass, "10").unwrap();
+        assert_eq!(heavy.mass, 10.0);
+        assert_eq!(heavy.kind, CelestialBody::default().with_mass(10.0).kind);
+        assert!(heavy.radius() > body().radius());
+
+        assert_eq!(edited(InspectorField::Mass, "0"), None);
+        assert_eq!(edited(InspectorField::Mass, "heavy"), None);
+    }
+
+    #[test]
+    fn trail_colours_are_hex() {
+        let units = SimulationUnits::default();
+        assert_eq!(
+            InspectorField::TrailColor.parse("#ff8000", &body(), &units),
+            Some(Edit::TrailColor(Srgba::rgb_u8(255, 128, 0).into()))
+        );
+        assert_eq!(
+            InspectorField::TrailColor.parse("orange", &body(), &units),
+            None
+        );
+        let trail = Trail::default().with_color(Srgba::rgb_u8(255, 128, 0).into());
+        assert_eq!(
+            InspectorField::TrailColor.value(&body(), Some(&trail), &units),
+            "#FF8000"
+        );
+    }
+}
@@ -0,0 +1,478 @@
+use bevy::prelude::*;
+
+use crate::celestial_body::CelestialBodyKind;
+use crate::orbital_elements::OrbitalElements;
+use crate::scenario::{BodyDescription, Scenario, ScenarioSettings, TrailSettings};
+use crate::units::SimulationUnits;
+
+/// Solar masses per kilogram.
+const SOLAR_MASSES_PER_KG: f32 = 1.0 / 1.988_47e30;
+/// Astronomical units per kilometer.
+const AU_PER_KM: f32 = 1.0 / 1.495_978_7e8;
+
+/// Mean elements of a planet at J2000, projected onto the ecliptic.
+struct Planet {
+    name: &'static str,
+    /// In AU.
+    semi_major_axis: f32,
+    eccentricity: f32,
+    /// Mean longitude in degrees.
+    mean_longitude: f32,
+    /// Longitude of perihelion in degrees.
+    longitude_of_perihelion: f32,
+    /// In solar masses.
+    mass: f32,
+    /// Equatorial radius in km.
+    radius: f32,
+    color: Srgba,
+}
+
+/// Mean elements of a moon around its planet at J2000.
+///
+/// The angles are measured in the planet's Laplace plane, or the ecliptic for the Moon, and laid
+/// flat like the planets' are.
+struct Moon {
+    name: &'static str,
+    planet: &'static str,
+    /// In km.
+    semi_major_axis: f32,
+    eccentricity: f32,
+    /// Mean longitude in degrees.
+    mean_longitude: f32,
+    /// Longitude of periapsis in degrees.
+    longitude_of_periapsis: f32,
+    /// In kg.
+    mass: f32,
+    /// In km.
+    radius: f32,
+    retrograde: bool,
+}
+
+const SUN_RADIUS_KM: f32 = 695_700.0;
+
+/// Keplerian elements from Standish, "Approximate Positions of the Planets", with the Earth-Moon
+/// barycentre standing in for the Earth.
+const PLANETS: [Planet; 8] = [
+    Planet {
+        name: "Mercury",
+        semi_major_axis: 0.387_099_27,
+        eccentricity: 0.205_635_93,
+        mean_longitude: 252.250_32,
+        longitude_of_perihelion: 77.457_8,
+        mass: 1.660_1e-7,
+        radius: 2_440.5,
+        color: Srgba::rgb(0.7, 0.7, 0.7),
+    },
+    Planet {
+        name: "Venus",
+        semi_major_axis: 0.723_335_7,
+        eccentricity: 0.006_776_72,
+        mean_longitude: 181.979_1,
+        longitude_of_perihelion: 131.602_47,
+        mass: 2.447_8e-6,
+        radius: 6_051.8,
+        color: Srgba::rgb(0.9, 0.8, 0.5),
+    },
+    Planet {
+        name: "Earth",
+        semi_major_axis: 1.000_002_6,
+        eccentricity: 0.016_711_23,
+        mean_longitude: 100.464_57,
+        longitude_of_perihelion: 102.937_68,
+        mass: 3.003_5e-6,
+        radius: 6_378.1,
+        color: Srgba::rgb(0.3, 0.5, 1.0),
+    },
+    Planet {
+        name: "Mars",
+        semi_major_axis: 1.523_710_4,
+        eccentricity: 0.093_394_1,
+        mean_longitude: -4.553_432,
+        longitude_of_perihelion: -23.943_63,
+        mass: 3.227_2e-7,
+        radius: 3_396.2,
+        color: Srgba::rgb(0.9, 0.4, 0.2),
+    },
+    Planet {
+        name: "Jupiter",
+        semi_major_axis: 5.202_887,
+        eccentricity: 0.048_386_24,
+        mean_longitude: 34.396_44,
+        longitude_of_perihelion: 14.728_48,
+        mass: 9.547_9e-4,
+        radius: 71_492.0,
+        color: Srgba::rgb(0.8, 0.6, 0.4),
+    },
+    Planet {
+        name: "Saturn",
+        semi_major_axis: 9.536_676,
+        eccentricity: 0.053_861_79,
+        mean_longitude: 49.954_24,
+        longitude_of_perihelion: 92.598_88,
+        mass: 2.858_9e-4,
+        radius: 60_268.0,
+        color: Srgba::rgb(0.9, 0.8, 0.6),
+    },
+    Planet {
+        name: "Uranus",
+        semi_major_axis: 19.189_165,
+        eccentricity: 0.047_257_44,
+        mean_longitude: 313.238_1,
+        longitude_of_perihelion: 170.954_28,
+        mass: 4.366_2e-5,
+        radius: 25_559.0,
+        color: Srgba::rgb(0.6, 0.9, 0.9),
+    },
+    Planet {
+        name: "Neptune",
+        semi_major_axis: 30.069_923,
+        eccentricity: 0.008_590_48,
+        mean_longitude: -55.120_03,
+        longitude_of_perihelion: 44.964_76,
+        mass: 5.151_4e-5,
+        radius: 24_764.0,
+        color: Srgba::rgb(0.3, 0.4, 0.9),
+    },
+];
+
+/// Mean elements from JPL's planetary satellite ephemerides, and Meeus' lunar theory for the Moon.
+const MOONS: [Moon; 8] = [
+    Moon {
+        name: "Moon",
+        planet: "Earth",
+        semi_major_axis: 384_400.0,
+        eccentricity: 0.054_9,
+        mean_longitude: 218.316_5,
+        longitude_of_periapsis: 83.353_2,
+        mass: 7.342e22,
+        radius: 1_737.4,
+        retrograde: false,
+    },
+    Moon {
+        name: "Io",
+        planet: "Jupiter",
+        semi_major_axis: 421_700.0,
+        eccentricity: 0.004_1,
+        mean_longitude: 20.0,
+        longitude_of_periapsis: 49.1,
+        mass: 8.932e22,
+        radius: 1_821.6,
+        retrograde: false,
+    },
+    Moon {
+        name: "Europa",
+        planet: "Jupiter",
+        semi_major_axis: 671_034.0,
+        eccentricity: 0.009,
+        mean_longitude: 214.4,
+        longitude_of_periapsis: 229.0,
+        mass: 4.8e22,
+        radius: 1_560.8,
+        retrograde: false,
+    },
+    Moon {
+        name: "Ganymede",
+        planet: "Jupiter",
+        semi_major_axis: 1_070_412.0,
+        eccentricity: 0.001_3,
+        mean_longitude: 221.6,
+        longitude_of_periapsis: 256.8,
+        mass: 1.481_9e23,
+        radius: 2_634.1,
+        retrograde: false,
+    },
+    Moon {
+        name: "Callisto",
+        planet: "Jupiter",
+        semi_major_axis: 1_882_709.0,
+        eccentricity: 0.007_4,
+        mean_longitude: 80.3,
+        longitude_of_periapsis: 352.9,
+        mass: 1.075_9e23,
+        radius: 2_410.3,
+        retrograde: false,
+    },
+    Moon {
+        name: "Titan",
+        planet: "Saturn",
+        semi_major_axis: 1_221_870.0,
+        eccentricity: 0.028_8,
+        mean_longitude: 11.902,
+        longitude_of_periapsis: 208.592,
+        mass: 1.345_2e23,
+        radius: 2_574.7,
+        retrograde: false,
+    },
+    Moon {
+        name: "Titania",
+        planet: "Uranus",
+        semi_major_axis: 435_910.0,
+        eccentricity: 0.001_1,
+        mean_longitude: 48.785,
+        longitude_of_periapsis: 24.171,
+        mass: 3.4e21,
+        radius: 788.4,
+        retrograde: false,
+    },
+    Moon {
+        name: "Triton",
+        planet: "Neptune",
+        semi_major_axis: 354_759.0,
+        eccentricity: 0.000_016,
+        mean_longitude: 236.007,
+        longitude_of_periapsis: 243.75,
+        mass: 2.14e22,
+        radius: 1_353.4,
+        retrograde: true,
+    },
+];
+
+/// Pixels per AU, which puts Neptune 30000 pixels out.
+const PIXELS_PER_AU: f32 = 1000.0;
+
+/// The Sun, planets and major moons at J2000, in AU, solar masses and days.
+///
+/// Each planet's elements place the barycentre of it and its moons relative to the Sun, then the
+/// whole system is shifted so its barycentre sits still at the origin.
+pub fn scenario() -> Scenario {
+    let units = SimulationUnits::astronomical(PIXELS_PER_AU);
+    let gravitational_constant = units.gravitational_constant() as f32;
+
+    let mut bodies = Vec::new();
+    for planet in &PLANETS {
+        let moons: Vec<&Moon> = MOONS
+            .iter()
+            .filter(|moon| moon.planet == planet.name)
+            .collect();
+
+        // Moons relative to their planet
+        let mut satellites = Vec::new();
+        for moon in moons {
+            let mass = moon.mass * SOLAR_MASSES_PER_KG;
+            let mu = gravitational_constant * (planet.mass + mass);
+            let longitude_of_periapsis = moon.longitude_of_periapsis.to_radians();
+            let (position, velocity) = OrbitalElements::from_mean_anomaly(
+                moon.semi_major_axis * AU_PER_KM,
+                moon.eccentricity,
+                longitude_of_periapsis,
+                moon.mean_longitude.to_radians() - longitude_of_periapsis,
+                moon.retrograde,
+                mu,
+            )
+            .to_state(mu);
+            satellites.push((moon, mass, position, velocity));
+        }
+
+        let system_mass = planet.mass + satellites.iter().map(|(_, mass, ..)| mass).sum::<f32>();
+        let mu = gravitational_constant * (1.0 + system_mass);
+        let longitude_of_perihelion = planet.longitude_of_perihelion.to_radians();
+        let (barycentre, barycentre_velocity) = OrbitalElements::from_mean_anomaly(
+            planet.semi_major_axis,
+            planet.eccentricity,
+            longitude_of_perihelion,
+            planet.mean_longitude.to_radians() - longitude_of_perihelion,
+            false,
+            mu,
+        )
+        .to_state(mu);
+
+        // Offset the planet against its moons so their barycentre follows the elements
+        let moment: Vec2 = satellites
+            .iter()
+            .map(|(_, mass, position, _)| *mass * *position)
+            .sum();
+        let momentum: Vec2 = satellites
+            .iter()
+            .map(|(_, mass, _, velocity)| *mass * *velocity)
+            .sum();
+        let position = barycentre - moment / system_mass;
+        let velocity = barycentre_velocity - momentum / system_mass;
+
+        bodies.push(BodyDescription {
+            name: planet.name.to_string(),
+            position,
+            velocity,
+            mass: planet.mass,
+            radius: Some(planet.radius * AU_PER_KM),
+            trail: TrailSettings {
+                enabled: true,
+                color: Some(planet.color),
+            },
+            kind: Some(CelestialBodyKind::Planet),
+            ..default()
+        });
+        for (moon, mass, moon_position, moon_velocity) in satellites {
+            bodies.push(BodyDescription {
+                name: moon.name.to_string(),
+                position: position + moon_position,
+                velocity: velocity + moon_velocity,
+                mass,
+                radius: Some(moon.radius * AU_PER_KM),
+                trail: TrailSettings {
+                    enabled: false,
+                    color: None,
+                },
+                kind: Some(CelestialBodyKind::Moon),
+                ..default()
+            });
+        }
+    }
+
+    bodies.insert(
+        0,
+        BodyDescription {
+            name: "Sun".to_string(),
+            position: Vec2::ZERO,
+            velocity: Vec2::ZERO,
+            mass: 1.0,
+            radius: Some(SUN_RADIUS_KM * AU_PER_KM),
+            trail: TrailSettings {
+                enabled: false,
+                color: None,
+            },
+            kind: Some(CelestialBodyKind::Star),
+            ..default()
+        },
+    );
+
+    // Move from heliocentric to barycentric coordinates
+    let mass: f32 = bodies.iter().map(|body| body.mass).sum();
+    let barycentre = bodies
+        .iter()
+        .map(|body| body.mass * body.position)
+        .sum::<Vec2>()
+        / mass;
+    let barycentre_velocity = bodies
+        .iter()
+        .map(|body| body.mass * body.velocity)
+        .sum::<Vec2>()
+        / mass;
+    for body in &mut bodies {
+        body.position -= barycentre;
+        body.velocity -= barycentre_velocity;
+    }
+
+    Scenario {
+        settings: ScenarioSettings {
+            gravitational_constant,
+            units,
+            // Inner planets, out to Jupiter, and everything
+            zoom_presets: vec![2.0, 6.0, 32.0],
+            ..default()
+        },
+        bodies,
+        generator: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::celestial_body::CelestialBody;
+    use crate::headless::headless_app;
+    use std::f32::consts::{PI, TAU};
+
+    fn body<'a>(scenario: &'a Scenario, name: &str) -> &'a BodyDescription {
+        scenario
+            .bodies
+            .iter()
+            .find(|body| body.name == name)
+            .unwrap()
+    }
+
+    /// The period of `name` around `primary`, in days.
+    fn period(scenario: &Scenario, name: &str, primary: &str) -> f32 {
+        let (body, primary) = (body(scenario, name), body(scenario, primary));
+        let mu = scenario.settings.gravitational_constant * (body.mass + primary.mass);
+        OrbitalElements::from_state(
+            body.position - primary.position,
+            body.velocity - primary.velocity,
+            mu,
+        )
+        .unwrap()
+        .period
+    }
+
+    #[test]
+    fn earth_orbits_in_a_year() {
+        let period = period(&scenario(), "Earth", "Sun");
+        assert!((period - 365.25).abs() < 0.001 * 365.25, "{period} days");
+    }
+
+    /// The direction from the Sun to the Earth in the running simulation.
+    fn earth_longitude(app: &mut App) -> f32 {
+        let world = app.world_mut();
+        let mut position = |name| {
+            world
+                .query::<&CelestialBody>()
+                .iter(world)
+                .find(|body| body.name == name)
+                .unwrap()
+                .position
+        };
+        (position("Earth") - position("Sun")).to_angle()
+    }
+
+    #[test]
+    fn earth_comes_back_after_a_simulated_year() {
+        // Coarser steps than the default keep the test quick, the moons still get their substeps
+        let mut scenario = scenario();
+        scenario.settings.timestep = 0.25;
+        let timestep = scenario.settings.timestep;
+        let mut app = headless_app(scenario);
+
+        // The first update only spawns the bodies
+        app.update();
+        let mut longitude = earth_longitude(&mut app);
+
+        // Unwrap the longitude step by step until it has gone all the way around
+        let mut travelled = 0.0;
+        let mut steps = 0;
+        while travelled < TAU {
+            app.update();
+            steps += 1;
+            let next = earth_longitude(&mut app);
+            travelled += (next - longitude + PI).rem_euclid(TAU) - PI;
+            longitude = next;
+        }
+        let days = steps as f32 * timestep;
+        // Within a day, as the other planets tug on the Earth too
+        assert!((days - 365.25).abs() < 1.0, "{days} days");
+    }
+
+    #[test]
+    fn moon_orbits_in_a_month() {
+        let period = period(&scenario(), "Moon", "Earth");
+        assert!((period - 27.32).abs() < 0.01 * 27.32, "{period} days");
+    }
+
+    #[test]
+    fn moon_starts_at_its_j2000_longitude() {
+        let scenario = scenario();
+        let (moon, earth) = (body(&scenario, "Moon"), body(&scenario, "Earth"));
+        assert_eq!(moon.kind, Some(CelestialBodyKind::Moon));
+
+        // The Moon's true geocentric longitude at J2000 was 223.3°, the mean elements leave out
+        // the perturbations from the Sun
+        let longitude = (moon.position - earth.position).to_angle().to_degrees();
+        let longitude = longitude.rem_euclid(360.0);
+        assert!((longitude - 223.3).abs() < 1.0, "{longitude}°");
+    }
+
+    #[test]
+    fn barycentre_is_at_rest_at_the_origin() {
+        let scenario = scenario();
+        let momentum: Vec2 = scenario
+            .bodies
+            .iter()
+            .map(|body| body.mass * body.velocity)
+            .sum();
+        let moment: Vec2 = scenario
+            .bodies
+            .iter()
+            .map(|body| body.mass * body.position)
+            .sum();
+        assert!(momentum.length() < 1.0e-9, "{momentum}");
+        assert!(moment.length() < 1.0e-7, "{moment}");
+    }
+}
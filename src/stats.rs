@@ -2,7 +2,15 @@ use bevy::diagnostic::{
     Diagnostic, DiagnosticPath, Diagnostics, DiagnosticsStore, FrameTimeDiagnosticsPlugin,
     RegisterDiagnostic,
 };
+use bevy::math::DVec2;
 use bevy::prelude::*;
+use bevy_rapier2d::prelude::{RapierConfiguration, TimestepMode};
+
+use crate::celestial_body::{resolve_collisions, CelestialBody};
+use crate::clock::{advance_clock, SimulationClock};
+use crate::gravity::GravityConfig;
+use crate::integrator::AdaptiveTimestep;
+use crate::units::SimulationUnits;
 
 #[cfg(debug_assertions)]
 use bevy::diagnostic::LogDiagnosticsPlugin;
@@ -18,16 +26,39 @@ impl Plugin for StatsPlugin {
         #[cfg(debug_assertions)]
         app.add_plugins(LogDiagnosticsPlugin::default()); // Adds print out to console
 
-        // Add a custom diagnostic
-        app.register_diagnostic(Diagnostic::new(REAL_TIME_RATE).with_suffix(" iterations"))
-            .add_systems(Update, update_custom_diagnostic);
-        // .add_systems(Update, print_custom_diagnostic);
+        // How fast the fixed step physics keeps up with the wall clock
+        app.register_diagnostic(Diagnostic::new(REAL_TIME_RATE).with_suffix("x"))
+            .register_diagnostic(Diagnostic::new(FIXED_STEPS_PER_FRAME))
+            .register_diagnostic(Diagnostic::new(DROPPED_TIME).with_suffix(" s"))
+            .register_diagnostic(Diagnostic::new(FIXED_OVERSTEP))
+            .register_diagnostic(Diagnostic::new(SUBSTEPS))
+            .register_diagnostic(Diagnostic::new(EFFECTIVE_TIMESTEP).with_suffix(" s"))
+            .init_resource::<FixedStepCounter>()
+            .add_systems(
+                FixedLast,
+                (count_fixed_steps, measure_substeps).before(advance_clock),
+            )
+            .add_systems(Update, update_real_time_rate);
+
+        // Conserved quantities, measured once the bodies have settled each fixed step
+        app.register_diagnostic(Diagnostic::new(KINETIC_ENERGY))
+            .register_diagnostic(Diagnostic::new(POTENTIAL_ENERGY))
+            .register_diagnostic(Diagnostic::new(LINEAR_MOMENTUM))
+            .register_diagnostic(Diagnostic::new(ANGULAR_MOMENTUM))
+            .init_resource::<Conservation>()
+            .add_systems(
+                FixedUpdate,
+                measure_conserved_quantities.after(resolve_collisions),
+            );
     }
 }
 
 #[derive(Component)]
 struct FpsText;
 
+#[derive(Component)]
+struct ConservationText;
+
 fn setup_fps_counter(mut commands: Commands) {
     commands
         .spawn(NodeBundle {
@@ -35,6 +66,8 @@ fn setup_fps_counter(mut commands: Commands) {
                 position_type: PositionType::Absolute,
                 top: Val::Px(5.0),
                 right: Val::Px(5.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::FlexEnd,
                 ..Default::default()
             },
             background_color: Color::NONE.into(),
@@ -54,34 +87,284 @@ fn setup_fps_counter(mut commands: Commands) {
                     ..Default::default()
                 })
                 .insert(FpsText);
+            parent
+                .spawn(TextBundle {
+                    text: Text::from_section(
+                        String::new(),
+                        TextStyle {
+                            font_size: 20.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    ),
+                    ..Default::default()
+                })
+                .insert(ConservationText);
         });
 }
 
 fn update_fps_counter(
     diagnostics: Res<DiagnosticsStore>,
+    conservation: Res<Conservation>,
+    clock: Res<SimulationClock>,
+    units: Res<SimulationUnits>,
     mut query: Query<&mut Text, With<FpsText>>,
+    mut conservation_query: Query<&mut Text, (With<ConservationText>, Without<FpsText>)>,
 ) {
     let fps = diagnostics.get(&FrameTimeDiagnosticsPlugin::FPS);
     let real_time = diagnostics.get(&REAL_TIME_RATE);
+    let steps = diagnostics.get(&FIXED_STEPS_PER_FRAME);
+    let substeps = diagnostics.get(&SUBSTEPS);
+    let effective_dt = diagnostics.get(&EFFECTIVE_TIMESTEP);
+    let dropped = diagnostics.get(&DROPPED_TIME);
+    let overstep = diagnostics.get(&FIXED_OVERSTEP);
 
-    if let (Some(fpsd), Some(real_timed)) = (fps, real_time) {
+    if let (Some(fpsd), Some(real_timed), Some(stepsd), Some(droppedd)) =
+        (fps, real_time, steps, dropped)
+    {
+        // Any time the fixed loop had to drop means it can't keep up
+        let falling_behind = droppedd.average().unwrap_or(0.0) > 0.0;
+        let latest = |diagnostic: Option<&Diagnostic>| diagnostic.and_then(Diagnostic::value);
+        let real_time_rate = real_timed.average().unwrap_or(0.0);
         for mut text in query.iter_mut() {
             text.sections[0].value = format!(
-                "FPS: {:.2}\nREAL TIME: {:.2}",
+                "FPS: {:.2}\nWARP: {}{:.2}x ({:.0}% REACHED){}\nREAL TIME: {:.2} {}/s\nSTEPS/FRAME: {:.2} (OVERSTEP {:.2})\nSUBSTEPS: {:.0} (dt {:.2e} {}){}",
                 fpsd.average().unwrap_or(0.0),
-                real_timed.average().unwrap_or(0.0)
+                if clock.reversed { "-" } else { "" },
+                clock.warp(),
+                // Falls short of the warp asked for once the fixed loop can't keep up
+                100.0 * real_time_rate / clock.warp() as f64,
+                if clock.paused { " (PAUSED)" } else { "" },
+                // Simulated time units per wall clock second
+                real_time_rate,
+                units.time.symbol,
+                stepsd.average().unwrap_or(0.0),
+                // The time accumulated towards the next step, as a fraction of a step
+                latest(overstep).unwrap_or(0.0),
+                latest(substeps).unwrap_or(1.0),
+                latest(effective_dt).unwrap_or(0.0),
+                units.time.symbol,
+                if falling_behind {
+                    "\nFALLING BEHIND"
+                } else {
+                    ""
+                }
             );
         }
     };
+
+    let (Some(baseline), Some(current)) = (conservation.baseline, conservation.latest) else {
+        return;
+    };
+    for mut text in conservation_query.iter_mut() {
+        text.sections[0].value = format!(
+            "KE: {:.4e}\nPE: {:.4e}\nE drift: {}\nP: {:.4e} drift: {}\nL: {:.4e} drift: {}",
+            current.kinetic_energy,
+            current.potential_energy,
+            drift(current.energy(), baseline.energy()),
+            current.linear_momentum.length(),
+            drift(
+                (current.linear_momentum - baseline.linear_momentum).length(),
+                baseline.linear_momentum.length()
+            ),
+            current.angular_momentum,
+            drift(current.angular_momentum, baseline.angular_momentum),
+        );
+    }
+}
+
+/// Formats the change from `baseline` as a percentage of it.
+fn drift(current: f64, baseline: f64) -> String {
+    if baseline == 0.0 {
+        return "n/a".to_string();
+    }
+    format!("{:+.3}%", 100.0 * (current - baseline) / baseline.abs())
 }
 
-// Add a measurement for REAL_TIME_RATE, note this should probably be the physics time ...
+/// Simulated seconds per wall clock second.
 const REAL_TIME_RATE: DiagnosticPath = DiagnosticPath::const_new("real_time_rate");
-fn update_custom_diagnostic(
+const FIXED_STEPS_PER_FRAME: DiagnosticPath = DiagnosticPath::const_new("fixed_steps_per_frame");
+/// Wall clock time the fixed loop gave up on this frame, because it was too far behind.
+const DROPPED_TIME: DiagnosticPath = DiagnosticPath::const_new("dropped_time");
+/// Fraction of a step left accumulated for the next frame.
+const FIXED_OVERSTEP: DiagnosticPath = DiagnosticPath::const_new("fixed_overstep");
+
+/// Substeps taken by the latest fixed step, more during close encounters.
+const SUBSTEPS: DiagnosticPath = DiagnosticPath::const_new("substeps");
+/// The fixed timestep divided by the number of substeps.
+const EFFECTIVE_TIMESTEP: DiagnosticPath = DiagnosticPath::const_new("effective_timestep");
+
+/// The physics steps run since the last frame.
+#[derive(Resource, Default)]
+struct FixedStepCounter {
+    steps: u32,
+    /// Seconds of simulated time, from the Rapier timestep.
+    simulated: f64,
+}
+
+fn count_fixed_steps(
+    mut counter: ResMut<FixedStepCounter>,
+    clock: Res<SimulationClock>,
+    rapier_config: Res<RapierConfiguration>,
+    time: Res<Time<Fixed>>,
+) {
+    counter.steps += 1;
+    if !clock.is_running() {
+        return;
+    }
+
+    let dt = match rapier_config.timestep_mode {
+        TimestepMode::Fixed { dt, .. } => dt,
+        _ => time.delta_seconds(),
+    };
+    counter.simulated += dt as f64 * clock.steps_per_tick() as f64;
+}
+
+fn measure_substeps(
+    mut diagnostics: Diagnostics,
+    clock: Res<SimulationClock>,
+    adaptive: Res<AdaptiveTimestep>,
+    time: Res<Time<Fixed>>,
+) {
+    if !clock.is_running() {
+        return;
+    }
+    let substeps = adaptive.last_substeps() as f64;
+    diagnostics.add_measurement(&SUBSTEPS, || substeps);
+    diagnostics.add_measurement(&EFFECTIVE_TIMESTEP, || time.delta_seconds_f64() / substeps);
+}
+
+fn update_real_time_rate(
     mut diagnostics: Diagnostics,
-    diagnostics_store: Res<DiagnosticsStore>,
+    mut counter: ResMut<FixedStepCounter>,
+    real_time: Res<Time<Real>>,
+    virtual_time: Res<Time<Virtual>>,
+    fixed_time: Res<Time<Fixed>>,
 ) {
-    if let Some(fps) = diagnostics_store.get(&FrameTimeDiagnosticsPlugin::FPS) {
-        diagnostics.add_measurement(&REAL_TIME_RATE, || fps.average().unwrap_or(0.0) / 60.0);
+    let wall = real_time.delta_seconds_f64();
+    if wall > 0.0 {
+        diagnostics.add_measurement(&REAL_TIME_RATE, || counter.simulated / wall);
+    }
+    diagnostics.add_measurement(&FIXED_STEPS_PER_FRAME, || counter.steps as f64);
+
+    // Virtual time is clamped to its max delta when a frame takes too long
+    let dropped = if virtual_time.is_paused() {
+        0.0
+    } else {
+        wall * virtual_time.effective_speed_f64() - virtual_time.delta_seconds_f64()
     };
+    diagnostics.add_measurement(&DROPPED_TIME, || dropped.max(0.0));
+    diagnostics.add_measurement(&FIXED_OVERSTEP, || fixed_time.overstep_fraction_f64());
+
+    *counter = FixedStepCounter::default();
+}
+
+const KINETIC_ENERGY: DiagnosticPath = DiagnosticPath::const_new("kinetic_energy");
+const POTENTIAL_ENERGY: DiagnosticPath = DiagnosticPath::const_new("potential_energy");
+const LINEAR_MOMENTUM: DiagnosticPath = DiagnosticPath::const_new("linear_momentum");
+const ANGULAR_MOMENTUM: DiagnosticPath = DiagnosticPath::const_new("angular_momentum");
+
+/// Totals over every body, in pixel units so they match the integrated motion.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConservedQuantities {
+    /// Translational plus spin kinetic energy.
+    pub kinetic_energy: f64,
+    pub potential_energy: f64,
+    pub linear_momentum: DVec2,
+    /// Orbital plus spin angular momentum about the origin.
+    pub angular_momentum: f64,
+}
+
+impl ConservedQuantities {
+    /// Sums the quantities over all bodies.
+    ///
+    /// The potential energy is an exact pairwise sum, whatever solver is in use.
+    pub fn from_bodies(
+        bodies: &[&CelestialBody],
+        gravity: &GravityConfig,
+        pixels_per_length: f32,
+    ) -> Self {
+        let mut quantities = Self::default();
+        for (i, body) in bodies.iter().enumerate() {
+            let mass = body.mass as f64;
+            let velocity = body.velocity.as_dvec2();
+            quantities.kinetic_energy += 0.5 * mass * velocity.length_squared()
+                + 0.5 * (body.moment_of_inertia() * body.spin.powi(2)) as f64;
+            quantities.linear_momentum += mass * velocity;
+            quantities.angular_momentum += body.angular_momentum(Vec2::ZERO, Vec2::ZERO) as f64;
+
+            // The same force law `apply_gravity` integrates, with distances in length units
+            for other in &bodies[i + 1..] {
+                let distance = body.position.distance(other.position) / pixels_per_length;
+                let potential = gravity.potential(body.mass, other.mass, distance);
+                quantities.potential_energy +=
+                    (pixels_per_length as f64).powi(2) * potential as f64;
+            }
+        }
+        quantities
+    }
+
+    pub fn energy(&self) -> f64 {
+        self.kinetic_energy + self.potential_energy
+    }
+}
+
+/// The conserved quantities at startup and after the latest fixed step.
+#[derive(Resource, Default)]
+struct Conservation {
+    baseline: Option<ConservedQuantities>,
+    latest: Option<ConservedQuantities>,
+}
+
+fn measure_conserved_quantities(
+    mut diagnostics: Diagnostics,
+    mut conservation: ResMut<Conservation>,
+    units: Res<SimulationUnits>,
+    gravity: Res<GravityConfig>,
+    query: Query<&CelestialBody>,
+) {
+    let bodies: Vec<&CelestialBody> = query.iter().collect();
+    if bodies.is_empty() {
+        return;
+    }
+
+    let pixels_per_length = units.pixels_per_length;
+    let quantities = ConservedQuantities::from_bodies(&bodies, &gravity, pixels_per_length);
+    diagnostics.add_measurement(&KINETIC_ENERGY, || quantities.kinetic_energy);
+    diagnostics.add_measurement(&POTENTIAL_ENERGY, || quantities.potential_energy);
+    diagnostics.add_measurement(&LINEAR_MOMENTUM, || quantities.linear_momentum.length());
+    diagnostics.add_measurement(&ANGULAR_MOMENTUM, || quantities.angular_momentum);
+
+    conservation.baseline.get_or_insert(quantities);
+    conservation.latest = Some(quantities);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totals_of_a_two_body_system() {
+        let gravity = GravityConfig::default();
+        let sun = CelestialBody::default()
+            .with_mass(10.0)
+            .with_velocity(Vec2::new(0.0, -1.0))
+            .with_spin(0.1);
+        let planet = CelestialBody::default()
+            .with_mass(1.0)
+            .with_position(Vec2::new(100.0, 0.0))
+            .with_velocity(Vec2::new(0.0, 10.0));
+
+        let quantities = ConservedQuantities::from_bodies(&[&sun, &planet], &gravity, 100.0);
+
+        let spin_energy = 0.5 * (sun.moment_of_inertia() * 0.01) as f64;
+        assert!(
+            (quantities.kinetic_energy - (0.5 * 10.0 + 0.5 * 100.0 + spin_energy)).abs() < 1.0e-6
+        );
+        // G m1 m2 ppm³ / r = 0.1 * 10 * 1 * 100³ / 100
+        assert!((quantities.potential_energy + 1.0e4).abs() < 1.0e-3);
+        assert!(quantities.linear_momentum.length() < 1.0e-9);
+        let spin_momentum = (sun.moment_of_inertia() * 0.1) as f64;
+        assert!((quantities.angular_momentum - (1000.0 + spin_momentum)).abs() < 1.0e-3);
+    }
 }
@@ -2,17 +2,30 @@ use bevy::prelude::*;
 
 use ringbuffer::{ConstGenericRingBuffer, RingBuffer};
 
+use crate::celestial_body::CelestialBody;
+use crate::clock::SimulationClock;
+use crate::frame::FrameView;
+
 pub struct TrailsPlugin;
 impl Plugin for TrailsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (update_trail_verticies, draw_trail).chain());
+        app.init_resource::<TrailHistory>()
+            .add_systems(Update, (update_trail_verticies, draw_trail).chain());
     }
 }
 
 const TRAIL_LENGTH: usize = 512;
+
+/// A point on a trail, in inertial coordinates, and the sample it was taken at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TrailVertex {
+    position: Vec2,
+    sample: u64,
+}
+
 #[derive(Component, Clone, Default, Debug)]
 pub struct Trail {
-    buffer: ConstGenericRingBuffer<Vec2, TRAIL_LENGTH>,
+    buffer: ConstGenericRingBuffer<TrailVertex, TRAIL_LENGTH>,
     color: Color,
     fadeout: FadeOut,
 }
@@ -31,40 +44,120 @@ impl Trail {
         }
     }
 
+    pub fn with_color(&self, color: Color) -> Self {
+        Self {
+            color,
+            ..self.clone()
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
+    /// Adds a vertex as part of the latest sample.
     pub fn add_vertex(&mut self, vertex: Vec2) {
-        self.buffer.push(vertex);
+        let sample = self.buffer.back().map_or(0, |last| last.sample);
+        self.buffer.push(TrailVertex {
+            position: vertex,
+            sample,
+        });
+    }
+}
+
+/// Where every body was at each of the latest trail samples.
+///
+/// Trails only record their own body, so this is what lets them be redrawn in a frame that turns
+/// with other bodies.
+#[derive(Resource, Default)]
+pub struct TrailHistory {
+    /// Sorted by entity, oldest first.
+    samples: ConstGenericRingBuffer<Vec<(Entity, Vec2)>, TRAIL_LENGTH>,
+    /// Id of the newest sample, counting from one.
+    latest: u64,
+}
+
+impl TrailHistory {
+    /// Records where the bodies are, returning the id of the new sample.
+    pub fn record(&mut self, mut positions: Vec<(Entity, Vec2)>) -> u64 {
+        positions.sort_by_key(|(entity, _)| *entity);
+        self.samples.push(positions);
+        self.latest += 1;
+        self.latest
+    }
+
+    /// Id of the oldest sample still held.
+    pub fn first_sample(&self) -> u64 {
+        self.latest + 1 - self.samples.len() as u64
+    }
+
+    /// The ids of the samples held, oldest first, with the positions in each.
+    pub fn samples(&self) -> impl Iterator<Item = (u64, &[(Entity, Vec2)])> {
+        let first = self.first_sample();
+        (first..).zip(self.samples.iter().map(Vec::as_slice))
+    }
+
+    /// Where a body was at a sample, if it existed then and the sample is still held.
+    pub fn position(&self, sample: u64, entity: Entity) -> Option<Vec2> {
+        let index = usize::try_from(sample.checked_sub(self.first_sample())?).ok()?;
+        let positions = self.samples.get(index)?;
+        let found = positions.binary_search_by_key(&entity, |(entity, _)| *entity);
+        found.ok().map(|index| positions[index].1)
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 enum FadeOut {
+    #[default]
     None,
     InSeconds(Timer),
 }
 
-impl Default for FadeOut {
-    fn default() -> Self {
-        Self::None
+/// Samples a vertex whenever simulated time has moved, in either direction
+fn update_trail_verticies(
+    clock: Res<SimulationClock>,
+    mut last_sampled: Local<Option<f64>>,
+    mut history: ResMut<TrailHistory>,
+    bodies: Query<(Entity, &CelestialBody)>,
+    mut query: Query<(&mut Trail, &Transform), With<CelestialBody>>,
+) {
+    let now = clock.elapsed_seconds();
+    if *last_sampled == Some(now) {
+        return;
     }
-}
+    *last_sampled = Some(now);
 
-fn update_trail_verticies(mut query: Query<(&mut Trail, &Transform)>) {
+    let sample = history.record(
+        bodies
+            .iter()
+            .map(|(entity, body)| (entity, body.position))
+            .collect(),
+    );
     for (mut trail, transform) in &mut query {
         let vert = Vec2::new(transform.translation.x, transform.translation.y);
-        trail.buffer.push(vert);
+        trail.buffer.push(TrailVertex {
+            position: vert,
+            sample,
+        });
     }
 }
 
 /// Draws a polyline for any entity that has a Trail component
-// FIXME: The clone here is probs really bad performance due to .into() on the trail.
 fn draw_trail(
     mut commands: Commands,
     time: Res<Time>,
     mut gizmos: Gizmos,
+    frame: FrameView,
     mut query: Query<(Entity, &mut Trail)>,
 ) {
+    // Trails are kept in inertial coordinates and moved into the frame as they are drawn
+    let projection = frame.trail_projection();
     for (entity, mut trail) in &mut query {
-        gizmos.linestrip_2d(trail.buffer.clone(), trail.color);
+        let vertices = trail
+            .buffer
+            .iter()
+            .filter_map(|vertex| projection.project(vertex.position, vertex.sample));
+        gizmos.linestrip_2d(vertices, trail.color);
 
         // Check how much time is left on a fadeout && despaw component if required
         if let FadeOut::InSeconds(ref mut timer) = trail.fadeout {
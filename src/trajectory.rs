@@ -0,0 +1,237 @@
+use bevy::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// The state of one body at one fixed step, in the scenario's units.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrajectorySample {
+    pub step: u64,
+    /// Simulated time in time units.
+    pub time: f32,
+    /// Stable identifier of the body, from its entity.
+    pub body: u64,
+    pub name: String,
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub mass: f32,
+}
+
+const COLUMNS: [&str; 9] = [
+    "step",
+    "time",
+    "body",
+    "name",
+    "position_x",
+    "position_y",
+    "velocity_x",
+    "velocity_y",
+    "mass",
+];
+
+/// Streams trajectory samples to disk, as CSV or (with the `parquet` feature) Parquet.
+pub enum TrajectoryWriter {
+    Csv(BufWriter<File>),
+    #[cfg(feature = "parquet")]
+    Parquet(Box<parquet::arrow::ArrowWriter<File>>),
+}
+
+pub type TrajectoryError = Box<dyn std::error::Error + Send + Sync>;
+
+impl TrajectoryWriter {
+    /// Creates the file, picking the format from the extension.
+    ///
+    /// Nothing is created if the format isn't available.
+    pub fn create(path: &Path) -> Result<Self, TrajectoryError> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            #[cfg(feature = "parquet")]
+            Some("parquet") => Ok(Self::Parquet(Box::new(
+                parquet::arrow::ArrowWriter::try_new(File::create(path)?, parquet_schema(), None)?,
+            ))),
+            #[cfg(not(feature = "parquet"))]
+            Some("parquet") => Err("built without the `parquet` feature".into()),
+            _ => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                writeln!(writer, "{}", COLUMNS.join(","))?;
+                Ok(Self::Csv(writer))
+            }
+        }
+    }
+
+    /// Writes the samples of a single step.
+    pub fn write(&mut self, samples: &[TrajectorySample]) -> Result<(), TrajectoryError> {
+        match self {
+            Self::Csv(writer) => {
+                for sample in samples {
+                    writeln!(
+                        writer,
+                        "{},{},{},\"{}\",{},{},{},{},{}",
+                        sample.step,
+                        sample.time,
+                        sample.body,
+                        sample.name.replace('"', "\"\""),
+                        sample.position.x,
+                        sample.position.y,
+                        sample.velocity.x,
+                        sample.velocity.y,
+                        sample.mass
+                    )?;
+                }
+            }
+            #[cfg(feature = "parquet")]
+            Self::Parquet(writer) => writer.write(&parquet_batch(samples)?)?,
+        }
+        Ok(())
+    }
+
+    /// Flushes everything to disk.
+    pub fn finish(self) -> Result<(), TrajectoryError> {
+        match self {
+            Self::Csv(mut writer) => writer.flush()?,
+            #[cfg(feature = "parquet")]
+            Self::Parquet(writer) => {
+                writer.close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_schema() -> std::sync::Arc<arrow_schema::Schema> {
+    use arrow_schema::{DataType, Field, Schema};
+
+    let data_type = |column| match column {
+        "step" | "body" => DataType::UInt64,
+        "name" => DataType::Utf8,
+        _ => DataType::Float32,
+    };
+    std::sync::Arc::new(Schema::new(
+        COLUMNS
+            .iter()
+            .map(|column| Field::new(*column, data_type(column), false))
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_batch(
+    samples: &[TrajectorySample],
+) -> Result<arrow_array::RecordBatch, arrow_schema::ArrowError> {
+    use arrow_array::{ArrayRef, Float32Array, StringArray, UInt64Array};
+    use std::sync::Arc;
+
+    let floats = |value: fn(&TrajectorySample) -> f32| -> ArrayRef {
+        Arc::new(Float32Array::from_iter_values(samples.iter().map(value)))
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(
+            samples.iter().map(|sample| sample.step),
+        )),
+        floats(|sample| sample.time),
+        Arc::new(UInt64Array::from_iter_values(
+            samples.iter().map(|sample| sample.body),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            samples.iter().map(|sample| &sample.name),
+        )),
+        floats(|sample| sample.position.x),
+        floats(|sample| sample.position.y),
+        floats(|sample| sample.velocity.x),
+        floats(|sample| sample.velocity.y),
+        floats(|sample| sample.mass),
+    ];
+    arrow_array::RecordBatch::try_new(parquet_schema(), columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A path in the temporary directory that no other test writes to.
+    fn temporary(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("bevy_solar_system_{}_{name}", std::process::id()))
+    }
+
+    fn samples() -> Vec<TrajectorySample> {
+        vec![
+            TrajectorySample {
+                step: 3,
+                time: 0.5,
+                body: 42,
+                name: "Sun".to_string(),
+                position: Vec2::new(1.0, -2.0),
+                velocity: Vec2::new(0.25, 0.0),
+                mass: 10.0,
+            },
+            TrajectorySample {
+                step: 3,
+                time: 0.5,
+                body: 43,
+                name: "Comet \"Halley\", 1P".to_string(),
+                position: Vec2::ZERO,
+                velocity: Vec2::new(-1.5, 3.0),
+                mass: 0.1,
+            },
+        ]
+    }
+
+    #[test]
+    fn csv_has_a_header_and_quotes_names() {
+        let path = temporary("trajectory.csv");
+        let mut writer = TrajectoryWriter::create(&path).unwrap();
+        writer.write(&samples()).unwrap();
+        writer.finish().unwrap();
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "step,time,body,name,position_x,position_y,velocity_x,velocity_y,mass",
+                "3,0.5,42,\"Sun\",1,-2,0.25,0,10",
+                "3,0.5,43,\"Comet \"\"Halley\"\", 1P\",0,0,-1.5,3,0.1",
+            ]
+        );
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    #[test]
+    fn parquet_without_the_feature_creates_nothing() {
+        let path = temporary("missing.parquet");
+        assert!(TrajectoryWriter::create(&path).is_err());
+        assert!(!path.exists());
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_round_trips_with_the_schema() {
+        use arrow_array::{Float32Array, StringArray, UInt64Array};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let path = temporary("trajectory.parquet");
+        let mut writer = TrajectoryWriter::create(&path).unwrap();
+        writer.write(&samples()).unwrap();
+        writer.finish().unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<_> = reader.map(Result::unwrap).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.schema(), parquet_schema());
+        let column = |name| batch.column_by_name(name).unwrap();
+        let bodies = column("body").as_any().downcast_ref::<UInt64Array>();
+        assert_eq!(bodies.unwrap().values(), &[42, 43]);
+        let names = column("name").as_any().downcast_ref::<StringArray>();
+        assert_eq!(names.unwrap().value(1), "Comet \"Halley\", 1P");
+        let velocities = column("velocity_y").as_any().downcast_ref::<Float32Array>();
+        assert_eq!(velocities.unwrap().values(), &[0.0, 3.0]);
+    }
+}
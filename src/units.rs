@@ -0,0 +1,143 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Newton's gravitational constant in m³ / (kg s²).
+const GRAVITATIONAL_CONSTANT_SI: f64 = 6.674_30e-11;
+
+/// A unit of measure, given by its size in SI units.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Unit {
+    pub symbol: String,
+    pub si: f64,
+}
+
+impl Unit {
+    pub fn new(symbol: &str, si: f64) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            si,
+        }
+    }
+}
+
+/// The scales the simulation is authored and integrated in.
+///
+/// Bodies live in pixels, so one length unit covers `pixels_per_length` pixels on screen and is
+/// the length unit Rapier works in. The simulated clock counts time units, and the gravitational
+/// constant is expressed in length³ / (mass time²).
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct SimulationUnits {
+    pub pixels_per_length: f32,
+    pub length: Unit,
+    pub mass: Unit,
+    pub time: Unit,
+}
+
+impl Default for SimulationUnits {
+    fn default() -> Self {
+        Self {
+            pixels_per_length: 100.0,
+            length: Unit::new("m", 1.0),
+            mass: Unit::new("kg", 1.0),
+            time: Unit::new("s", 1.0),
+        }
+    }
+}
+
+impl SimulationUnits {
+    /// Astronomical units, solar masses and days, with `pixels_per_length` pixels per AU.
+    pub fn astronomical(pixels_per_length: f32) -> Self {
+        Self {
+            pixels_per_length,
+            length: Unit::new("AU", 1.495_978_707e11),
+            mass: Unit::new("M☉", 1.988_47e30),
+            time: Unit::new("d", 86_400.0),
+        }
+    }
+
+    /// The real gravitational constant, in length³ / (mass time²).
+    pub fn gravitational_constant(&self) -> f64 {
+        GRAVITATIONAL_CONSTANT_SI * self.mass.si * self.time.si.powi(2) / self.length.si.powi(3)
+    }
+
+    /// Converts a position in length units, or a velocity in length units per time unit, to pixels.
+    pub fn units_to_pixels(&self, value: Vec2) -> Vec2 {
+        value * self.pixels_per_length
+    }
+
+    /// The inverse of [`Self::units_to_pixels`].
+    pub fn pixels_to_units(&self, value: Vec2) -> Vec2 {
+        value / self.pixels_per_length
+    }
+
+    /// A distance in pixels, in length units with the unit symbol.
+    pub fn format_length(&self, pixels: f32) -> String {
+        format!(
+            "{:.3} {}",
+            pixels / self.pixels_per_length,
+            self.length.symbol
+        )
+    }
+
+    pub fn format_time(&self, time: f32) -> String {
+        format!("{:.2} {}", time, self.time.symbol)
+    }
+}
+
+/// How close bodies get before Rapier reports a contact, in pixels.
+///
+/// Rapier's default at 100 pixels per length unit. Keeping it fixed in pixels stops bodies far
+/// smaller than a length unit, like moons at AU scale, from colliding with near misses.
+const PREDICTION_DISTANCE: f32 = 0.2;
+
+/// Keeps Rapier's length unit in step with the simulation units
+pub fn apply_length_unit(units: Res<SimulationUnits>, mut rapier_context: ResMut<RapierContext>) {
+    if units.is_changed() {
+        let parameters = &mut rapier_context.integration_parameters;
+        parameters.length_unit = units.pixels_per_length;
+        parameters.normalized_prediction_distance = PREDICTION_DISTANCE / units.pixels_per_length;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn astronomical() -> SimulationUnits {
+        SimulationUnits::astronomical(200.0)
+    }
+
+    #[test]
+    fn pixel_conversions_round_trip() {
+        let units = astronomical();
+        let position = Vec2::new(1.5, -0.25);
+        assert_eq!(units.units_to_pixels(position), Vec2::new(300.0, -50.0));
+        assert_eq!(
+            units.pixels_to_units(units.units_to_pixels(position)),
+            position
+        );
+    }
+
+    #[test]
+    fn gravitational_constant_in_other_units() {
+        assert_eq!(
+            SimulationUnits::default().gravitational_constant(),
+            GRAVITATIONAL_CONSTANT_SI
+        );
+        // The Gaussian gravitational constant squared, in AU³ / (M☉ d²)
+        let g = astronomical().gravitational_constant();
+        assert!(
+            (g / 0.017_202_098_95_f64.powi(2) - 1.0).abs() < 1.0e-4,
+            "{g}"
+        );
+    }
+
+    #[test]
+    fn readouts_use_the_unit_symbols() {
+        let units = astronomical();
+        assert_eq!(units.format_length(100.0), "0.500 AU");
+        assert_eq!(units.format_time(365.25), "365.25 d");
+    }
+}